@@ -5,14 +5,23 @@ pub struct MmapAllocator;
 #[global_allocator]
 pub static ALLOCATOR: MmapAllocator = MmapAllocator;
 
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
 unsafe impl GlobalAlloc for MmapAllocator {
     unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        // mmap only guarantees page alignment, so larger alignments over-map by the alignment
+        // and then unmap the unaligned head and the leftover tail. Only the aligned range stays
+        // mapped, so dealloc can unmap from the pointer it's given.
+        let extra = if layout.align() > page_size() { layout.align() } else { 0 };
+
         let ptr =
             match libc::mmap(
                 null_mut(),
-                layout.size(),
+                layout.size() + extra,
                 libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
                 -1,
                 0,
             ) {
@@ -20,7 +29,25 @@ unsafe impl GlobalAlloc for MmapAllocator {
                 ptr => ptr as *mut u8,
             };
 
-        ptr
+        if extra == 0 {
+            return ptr;
+        }
+
+        let head = ptr.align_offset(layout.align());
+        let aligned = ptr.add(head);
+
+        let mapped_end = layout.size() + extra;
+        let aligned_end = (head + layout.size()).next_multiple_of(page_size());
+
+        if head > 0 {
+            libc::munmap(ptr as *mut c_void, head);
+        }
+
+        if mapped_end > aligned_end {
+            libc::munmap(ptr.add(aligned_end) as *mut c_void, mapped_end - aligned_end);
+        }
+
+        aligned
     }
     
     unsafe fn alloc_zeroed(&self, layout: std::alloc::Layout) -> *mut u8 {
@@ -33,4 +60,118 @@ unsafe impl GlobalAlloc for MmapAllocator {
     unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
         libc::munmap(ptr as *mut c_void, layout.size());
     }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
+        // Remapping moves the existing pages rather than faulting in and copying to new ones,
+        // but it only keeps page alignment. Allocators mustn't unwind, so a failure is reported
+        // as null for handle_alloc_error, leaving the old mapping in place.
+        #[cfg(target_os = "linux")]
+        if layout.align() <= page_size() {
+            return match libc::mremap(ptr as *mut c_void, layout.size(), new_size, libc::MREMAP_MAYMOVE) {
+                libc::MAP_FAILED => null_mut(),
+                ptr => ptr as *mut u8,
+            };
+        }
+
+        let new_layout = std::alloc::Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+
+        std::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+        self.dealloc(ptr, layout);
+
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout};
+
+    use super::ALLOCATOR;
+
+    #[repr(align(8192))]
+    struct TwoPages([u8; 8192]);
+
+    #[test]
+    fn test_over_aligned_type() {
+        let boxed = Box::new(TwoPages([7; 8192]));
+
+        assert_eq!(&*boxed as *const _ as usize % 8192, 0);
+        assert!(boxed.0.iter().all(|b| *b == 7));
+    }
+
+    #[test]
+    fn test_vec_growth() {
+        let mut v = Vec::new();
+        let mut capacities = Vec::new();
+
+        for i in 0..1_000_000u32 {
+            if v.len() == v.capacity() {
+                capacities.push(v.capacity());
+            }
+
+            v.push(i);
+        }
+
+        // Every doubling past the first allocation is a realloc
+        assert!(capacities.len() > 10);
+        assert!(v.iter().enumerate().all(|(i, x)| *x == i as u32));
+
+        v.truncate(1000);
+        v.shrink_to_fit();
+        assert!(v.iter().enumerate().all(|(i, x)| *x == i as u32));
+    }
+
+    #[test]
+    fn test_over_aligned_realloc() {
+        let layout = Layout::from_size_align(10_000, 1 << 16).unwrap();
+
+        unsafe {
+            let ptr = ALLOCATOR.alloc(layout);
+            std::slice::from_raw_parts_mut(ptr, 10_000).fill(0xcd);
+
+            let ptr = ALLOCATOR.realloc(ptr, layout, 100_000);
+            assert_eq!(ptr as usize % (1 << 16), 0);
+            assert!(std::slice::from_raw_parts(ptr, 10_000).iter().all(|b| *b == 0xcd));
+
+            ALLOCATOR.dealloc(ptr, Layout::from_size_align(100_000, 1 << 16).unwrap());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_failed_realloc_returns_null() {
+        let layout = Layout::from_size_align(4096, 8).unwrap();
+
+        unsafe {
+            let ptr = ALLOCATOR.alloc(layout);
+            ptr.write(0x5a);
+
+            // Far more than the address space can hold, so mremap has to fail
+            assert!(ALLOCATOR.realloc(ptr, layout, isize::MAX as usize).is_null());
+            assert_eq!(ptr.read(), 0x5a);
+
+            ALLOCATOR.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn test_over_aligned_layouts() {
+        for align in [4096, 8192, 1 << 16, 1 << 21] {
+            for size in [1, 4096, 10_000, 1 << 20] {
+                let layout = Layout::from_size_align(size, align).unwrap();
+
+                unsafe {
+                    let ptr = ALLOCATOR.alloc_zeroed(layout);
+                    assert_eq!(ptr as usize % align, 0, "size {size}, align {align}");
+
+                    let data = std::slice::from_raw_parts_mut(ptr, size);
+                    assert!(data.iter().all(|b| *b == 0));
+                    data.fill(0xab);
+
+                    ALLOCATOR.dealloc(ptr, layout);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,260 @@
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    ffi::c_void,
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+use crate::util::GB;
+
+/// Address space reserved for the global arena. Pages are only backed once touched, so this just
+/// needs to be more than a run will ever allocate.
+pub const ARENA_CAPACITY: usize = 16 * GB;
+
+/// Bump allocator over one mapping made up front, so the allocations of a run fault in as a single
+/// contiguous sweep instead of a mapping each. Freeing does nothing, memory is only given back by
+/// `reset`.
+pub struct ArenaAllocator {
+    capacity: usize,
+    base: AtomicPtr<u8>,
+    used: AtomicUsize,
+}
+
+#[global_allocator]
+pub static ARENA: ArenaAllocator = ArenaAllocator::new(ARENA_CAPACITY);
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+impl ArenaAllocator {
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            base: AtomicPtr::new(null_mut()),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// The mapping is made on first use, since statics can't call mmap. Null if it failed.
+    fn base(&self) -> *mut u8 {
+        let base = self.base.load(Ordering::Acquire);
+        if !base.is_null() {
+            return base;
+        }
+
+        let mapped = match unsafe {
+            libc::mmap(
+                null_mut(),
+                self.capacity,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        } {
+            libc::MAP_FAILED => return null_mut(),
+            ptr => ptr as *mut u8,
+        };
+
+        match self
+            .base
+            .compare_exchange(null_mut(), mapped, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => mapped,
+            Err(winner) => {
+                unsafe { libc::munmap(mapped as *mut c_void, self.capacity) };
+                winner
+            }
+        }
+    }
+
+    /// How far into the arena allocations have reached, to later `reset` back to
+    pub fn checkpoint(&self) -> usize {
+        self.used.load(Ordering::Acquire)
+    }
+
+    /// Releases everything allocated since `checkpoint`. Whole pages are remapped, so the next
+    /// run faults them in again just like the first.
+    ///
+    /// # Safety
+    ///
+    /// Nothing allocated after `checkpoint` was taken may still be in use
+    pub unsafe fn reset(&self, checkpoint: usize) {
+        let base = self.base.load(Ordering::Acquire);
+        let used = self.used.swap(checkpoint, Ordering::AcqRel);
+
+        if base.is_null() || used <= checkpoint {
+            return;
+        }
+
+        // Everything past `used` has to read as zero for alloc_zeroed, including the partial
+        // page the checkpoint lands in
+        let first_page = checkpoint.next_multiple_of(page_size()).min(used);
+        std::ptr::write_bytes(base.add(checkpoint), 0, first_page - checkpoint);
+
+        if used > first_page {
+            libc::mmap(
+                base.add(first_page) as *mut c_void,
+                used - first_page,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE | libc::MAP_FIXED,
+                -1,
+                0,
+            );
+        }
+    }
+}
+
+impl Drop for ArenaAllocator {
+    fn drop(&mut self) {
+        let base = *self.base.get_mut();
+        if !base.is_null() {
+            unsafe { libc::munmap(base as *mut c_void, self.capacity) };
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for ArenaAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let base = self.base();
+        if base.is_null() {
+            return null_mut();
+        }
+
+        let mut used = self.used.load(Ordering::Acquire);
+        loop {
+            let start = (base as usize + used).next_multiple_of(layout.align()) - base as usize;
+            let end = start + layout.size();
+
+            if end > self.capacity {
+                return null_mut();
+            }
+
+            match self
+                .used
+                .compare_exchange_weak(used, end, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return base.add(start),
+                Err(actual) => used = actual,
+            }
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        // Memory past the bump pointer is either fresh from mmap or was zeroed by reset
+        self.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // Shrinking never moves the bump pointer back, as that would leave dirty memory past it
+        if new_size <= layout.size() {
+            return ptr;
+        }
+
+        // The latest allocation has nothing after it, so it can grow in place. This is the usual
+        // case for a Vec being pushed to while parsing.
+        let offset = ptr as usize - self.base.load(Ordering::Acquire) as usize;
+        let end = offset + layout.size();
+        if offset + new_size <= self.capacity
+            && self
+                .used
+                .compare_exchange(end, offset + new_size, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            return ptr;
+        }
+
+        let new_ptr = self.alloc(Layout::from_size_align_unchecked(new_size, layout.align()));
+        if !new_ptr.is_null() {
+            std::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size());
+        }
+
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout};
+
+    use crate::{
+        generate::{gen_input_seeded, Distribution},
+        parse::JsonValue,
+    };
+
+    use super::{ArenaAllocator, ARENA};
+
+    #[test]
+    fn test_bump_and_reset() {
+        let arena = ArenaAllocator::new(1 << 20);
+        let layout = Layout::from_size_align(10_000, 64).unwrap();
+
+        unsafe {
+            let first = arena.alloc(layout);
+            assert_eq!(first as usize % 64, 0);
+            std::slice::from_raw_parts_mut(first, 10_000).fill(0xab);
+
+            let checkpoint = arena.checkpoint();
+            let second = arena.alloc(layout);
+            assert!(second >= first.add(10_000));
+            std::slice::from_raw_parts_mut(second, 10_000).fill(0xcd);
+
+            arena.reset(checkpoint);
+            assert_eq!(arena.checkpoint(), checkpoint);
+
+            let again = arena.alloc_zeroed(layout);
+            assert_eq!(again, second);
+            assert!(std::slice::from_raw_parts(again, 10_000).iter().all(|b| *b == 0));
+            assert!(std::slice::from_raw_parts(first, 10_000).iter().all(|b| *b == 0xab));
+        }
+    }
+
+    #[test]
+    fn test_grow_last_in_place() {
+        let arena = ArenaAllocator::new(1 << 20);
+        let layout = Layout::from_size_align(100, 8).unwrap();
+
+        unsafe {
+            let ptr = arena.alloc(layout);
+            std::slice::from_raw_parts_mut(ptr, 100).fill(7);
+
+            let grown = arena.realloc(ptr, layout, 50_000);
+            assert_eq!(grown, ptr);
+            assert_eq!(arena.checkpoint(), 50_000);
+
+            // Once something comes after it, growing has to move
+            arena.alloc(layout);
+            let moved = arena.realloc(grown, Layout::from_size_align(50_000, 8).unwrap(), 60_000);
+            assert_ne!(moved, grown);
+            assert!(std::slice::from_raw_parts(moved, 100).iter().all(|b| *b == 7));
+
+            assert!(arena.alloc(Layout::from_size_align(1 << 20, 8).unwrap()).is_null());
+        }
+    }
+
+    #[test]
+    fn test_parse_in_arena() {
+        const SAMPLES: u64 = 10_000;
+
+        let tmpfile = tempfile::NamedTempFile::new().unwrap();
+        let path = tmpfile.path().to_str().unwrap();
+        gen_input_seeded(path, Distribution::Cluster(4), SAMPLES, 42).expect("Failed to generate input");
+
+        let data = std::fs::read_to_string(path).unwrap();
+        let before = ARENA.checkpoint();
+        let json = JsonValue::parse(&data);
+
+        assert!(ARENA.checkpoint() > before);
+
+        let pairs = json["pairs"].elements();
+        assert_eq!(pairs.len(), SAMPLES as usize);
+        for pair in pairs {
+            let keys: Vec<_> = pair.items().iter().map(|(k, _)| *k).collect();
+            assert_eq!(keys, ["x0", "y0", "x1", "y1"]);
+            assert!(pair.items().iter().all(|(_, v)| matches!(v, JsonValue::Number(_))));
+        }
+    }
+}
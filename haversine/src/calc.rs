@@ -1,14 +1,34 @@
-use std::io::{self};
+use std::io::{self, Read};
 
 #[cfg(feature = "profile")]
 use std::os::unix::fs::MetadataExt;
 
 use profiler_macro::{instr, instrument};
 
-use crate::{parse::JsonValue, read_to_string_fast, EARTH_RADIUS};
+use crate::{
+    haversine_close, haversine_epsilon,
+    math::{HaversineMath, PolyMath, StdMath},
+    parse::{JsonValue, PairStream},
+    read_to_string_fast,
+    simd::F64x2,
+    EARTH_RADIUS,
+};
 
-#[instrument]
 pub fn average_haversine(path: &str) -> io::Result<(usize, f64)> {
+    average_haversine_with::<StdMath>(path)
+}
+
+/// `average_haversine` with the sin/cos/asin/sqrt of `M`, e.g.
+/// `average_haversine_with::<PolyMath>(path)` to profile the approximations against std
+pub fn average_haversine_with<M: HaversineMath>(path: &str) -> io::Result<(usize, f64)> {
+    let (size, _, average) = average_haversine_counted::<M>(path)?;
+    Ok((size, average))
+}
+
+/// `average_haversine_with`, also returning how many pairs there were between the input size
+/// and the average
+#[instrument("average_haversine")]
+pub fn average_haversine_counted<M: HaversineMath>(path: &str) -> io::Result<(usize, usize, f64)> {
 
     let data;
 
@@ -17,7 +37,7 @@ pub fn average_haversine(path: &str) -> io::Result<(usize, f64)> {
         data = read_to_string_fast(&mut infile);
     });
 
-    let json = JsonValue::parse(&data);
+    let json = JsonValue::try_parse(&data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
 
     let mut sum = 0.0;
     let pairs = json["pairs"].elements();
@@ -29,14 +49,189 @@ pub fn average_haversine(path: &str) -> io::Result<(usize, f64)> {
             let x1 = &pair["x1"];
             let y1 = &pair["y1"];
 
-            sum += haversine(x0.into(), y0.into(), x1.into(), y1.into());
+            sum += haversine_with::<M>(x0.into(), y0.into(), x1.into(), y1.into());
+        }
+    });
+
+    Ok((data.len(), pairs.len(), sum / pairs.len() as f64))
+}
+
+/// Same result as `average_haversine`, but sums each pair as it's parsed instead of building the
+/// whole JSON tree first
+#[instrument]
+pub fn average_haversine_streaming(path: &str) -> io::Result<(usize, f64)> {
+
+    let data;
+
+    let mut infile = std::fs::File::open(path)?;
+    instr!("Read", infile.metadata()?.size(), {
+        data = read_to_string_fast(&mut infile);
+    });
+
+    let mut sum = 0.0;
+    let mut count: usize = 0;
+    instr!("Parse + Sum", data.len(), {
+        for pair in PairStream::new(&data) {
+            let (x0, y0, x1, y1) = pair.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+            sum += haversine(x0, y0, x1, y1);
+            count += 1;
+        }
+    });
+
+    Ok((data.len(), sum / count as f64))
+}
+
+/// Computes the average over a file written by `gen_input_binary`
+#[instrument]
+pub fn average_haversine_binary(path: &str) -> io::Result<(usize, f64)> {
+
+    let mut data = Vec::new();
+
+    let mut infile = std::fs::File::open(path)?;
+    instr!("Read", infile.metadata()?.size(), {
+        infile.read_to_end(&mut data)?;
+    });
+
+    let (header, pairs) = data
+        .split_first_chunk::<8>()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing sample count header"))?;
+
+    let samples = u64::from_le_bytes(*header) as usize;
+    // The count comes straight from the file, so a corrupt one mustn't be able to overflow
+    if samples.checked_mul(4 * size_of::<f64>()) != Some(pairs.len()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Expected {samples} pairs, found {} bytes of pair data", pairs.len()),
+        ));
+    }
+
+    let mut sum = 0.0;
+    instr!("Sum", pairs.len(), {
+        for pair in pairs.chunks_exact(4 * size_of::<f64>()) {
+            let mut coords = pair
+                .chunks_exact(size_of::<f64>())
+                .map(|c| f64::from_le_bytes(c.try_into().unwrap()));
+
+            let mut next = || coords.next().unwrap();
+            sum += haversine(next(), next(), next(), next());
         }
     });
 
-    Ok((data.len(), sum / pairs.len() as f64))
+    Ok((data.len(), sum / samples as f64))
+}
+
+/// Largest and mean absolute difference between each pair's distance and its reference answer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnswerError {
+    pub max: f64,
+    pub mean: f64,
+}
+
+/// `average_haversine`, also comparing each pair against the answers file from
+/// `gen_input_with_answers` to track down numerical drift
+#[instrument]
+pub fn average_haversine_with_answers(path: &str, answers_path: &str) -> io::Result<(usize, f64, AnswerError)> {
+
+    let data;
+
+    let mut infile = std::fs::File::open(path)?;
+    instr!("Read", infile.metadata()?.size(), {
+        data = read_to_string_fast(&mut infile);
+    });
+
+    let json = JsonValue::try_parse(&data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let pairs = json["pairs"].elements();
+    let answers = read_answers(answers_path, pairs.len())?;
+
+    let mut sum = 0.0;
+    let mut error = AnswerError { max: 0.0, mean: 0.0 };
+    instr!("Sum", pairs.len() * 4 * size_of::<f64>(), {
+        for (pair, expected) in pairs.iter().zip(&answers) {
+            let distance = haversine(
+                (&pair["x0"]).into(),
+                (&pair["y0"]).into(),
+                (&pair["x1"]).into(),
+                (&pair["y1"]).into(),
+            );
+            sum += distance;
+
+            let diff = (distance - expected).abs();
+            error.max = error.max.max(diff);
+            error.mean += diff;
+        }
+    });
+    error.mean /= pairs.len() as f64;
+
+    Ok((data.len(), sum / pairs.len() as f64, error))
+}
+
+/// Every answer from an answers file for `pairs` pairs, with the average last
+fn read_answers(answers_path: &str, pairs: usize) -> io::Result<Vec<f64>> {
+    let answers = std::fs::read(answers_path)?;
+    if answers.len() != (pairs + 1) * size_of::<f64>() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Expected {} answers, found {} bytes", pairs + 1, answers.len()),
+        ));
+    }
+
+    Ok(answers
+        .chunks_exact(size_of::<f64>())
+        .map(|c| f64::from_le_bytes(c.try_into().unwrap()))
+        .collect())
+}
+
+/// Recomputes every pair in `json_path` and compares it, and the average, against an answers
+/// file from `gen_input_with_answers`. Mismatches are printed, and the result is whether all matched.
+pub fn check_answers(json_path: &str, answers_path: &str) -> io::Result<bool> {
+    let data = read_to_string_fast(&mut std::fs::File::open(json_path)?);
+    let json = JsonValue::try_parse(&data)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    let pairs = json["pairs"].elements();
+
+    let mut answers = read_answers(answers_path, pairs.len())?.into_iter();
+
+    let epsilon = haversine_epsilon();
+    let mut matched = true;
+    let mut sum = 0.0;
+    for (i, (pair, expected)) in pairs.iter().zip(&mut answers).enumerate() {
+        let actual = haversine(
+            (&pair["x0"]).into(),
+            (&pair["y0"]).into(),
+            (&pair["x1"]).into(),
+            (&pair["y1"]).into(),
+        );
+        sum += actual;
+
+        if !haversine_close(expected, actual, epsilon) {
+            println!("Pair {i}: expected {expected}, got {actual}");
+            matched = false;
+        }
+    }
+
+    let expected = answers.next().unwrap();
+    let actual = sum / pairs.len() as f64;
+    if !haversine_close(expected, actual, epsilon) {
+        println!("Average: expected {expected}, got {actual}");
+        matched = false;
+    }
+
+    Ok(matched)
 }
 
 fn haversine(x0: f64, y0: f64, x1: f64, y1: f64) -> f64 {
+    haversine_with::<StdMath>(x0, y0, x1, y1)
+}
+
+/// `haversine` on `PolyMath`'s polynomials instead of libm, instrumented so `profile_report` shows
+/// what it costs next to the reference
+#[instrument]
+pub fn haversine_approx(x0: f64, y0: f64, x1: f64, y1: f64) -> f64 {
+    haversine_with::<PolyMath>(x0, y0, x1, y1)
+}
+
+pub fn haversine_with<M: HaversineMath>(x0: f64, y0: f64, x1: f64, y1: f64) -> f64 {
 
     let d_lat = (y1 - y0).to_radians();
     let d_lon = (x1 - x0).to_radians();
@@ -47,32 +242,250 @@ fn haversine(x0: f64, y0: f64, x1: f64, y1: f64) -> f64 {
         x * x
     }
 
-    let a = square((d_lat/2.0).sin()) + lat1.cos() * lat2.cos() * square((d_lon/2.0).sin());
+    let a = square(M::sin(d_lat/2.0)) + M::cos(lat1) * M::cos(lat2) * square(M::sin(d_lon/2.0));
 
-    let c = 2.0 * a.sqrt().asin();
+    let c = 2.0 * M::asin(M::sqrt(a));
 
     c * EARTH_RADIUS
 }
 
+/// Sum of the distances between each `(xs0[i], ys0[i])` and `(xs1[i], ys1[i])`, worked out
+/// `F64x2::LANES` pairs at a time with polynomial sin/cos/asin instead of libm. `haversine` is
+/// still the reference, any leftover pairs go through it.
+pub fn haversine_batch(xs0: &[f64], ys0: &[f64], xs1: &[f64], ys1: &[f64]) -> f64 {
+    let len = xs0.len();
+    assert!(
+        ys0.len() == len && xs1.len() == len && ys1.len() == len,
+        "Coordinate slices differ in length"
+    );
+
+    let to_radians = F64x2::splat(std::f64::consts::PI / 180.0);
+    let half = F64x2::splat(0.5);
+    let one = F64x2::splat(1.0);
+
+    let batched = len - len % F64x2::LANES;
+    let mut sum = F64x2::splat(0.0);
+
+    for i in (0..batched).step_by(F64x2::LANES) {
+        let x0 = F64x2::from_slice(&xs0[i..]);
+        let y0 = F64x2::from_slice(&ys0[i..]);
+        let x1 = F64x2::from_slice(&xs1[i..]);
+        let y1 = F64x2::from_slice(&ys1[i..]);
+
+        let sin_d_lat = ((y1 - y0) * to_radians * half).sin();
+        let sin_d_lon = ((x1 - x0) * to_radians * half).sin();
+        let cos_lats = (y0 * to_radians).cos() * (y1 * to_radians).cos();
+
+        let a = sin_d_lat * sin_d_lat + cos_lats * sin_d_lon * sin_d_lon;
+
+        // Approximation error can nudge nearly antipodal points just past 1, out of asin's domain
+        let a = a.select_gt(one, one, a);
+
+        sum = sum + a.sqrt().asin();
+    }
+
+    let mut sum = 2.0 * EARTH_RADIUS * sum.sum();
+    for i in batched..len {
+        sum += haversine(xs0[i], ys0[i], xs1[i], ys1[i]);
+    }
+
+    sum
+}
+
+/// Sum of the distances of every `[x0, y0, x1, y1]` pair, through `haversine_batch` with the
+/// `simd` feature and one pair at a time through `haversine` without
+pub fn haversine_sum(pairs: &[[f64; 4]]) -> f64 {
+    #[cfg(feature = "simd")]
+    {
+        // One slice per coordinate, so each batch loads straight into the lanes
+        let mut coords: [Vec<f64>; 4] = Default::default();
+        for pair in pairs {
+            for (coord, x) in coords.iter_mut().zip(pair) {
+                coord.push(*x);
+            }
+        }
+
+        let [xs0, ys0, xs1, ys1] = &coords;
+        haversine_batch(xs0, ys0, xs1, ys1)
+    }
+
+    #[cfg(not(feature = "simd"))]
+    pairs.iter().map(|&[x0, y0, x1, y1]| haversine(x0, y0, x1, y1)).sum()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::test_samples;
+    use super::{average_haversine, average_haversine_binary, average_haversine_streaming, average_haversine_with_answers, check_answers, haversine, haversine_approx, haversine_batch, haversine_sum};
+    use crate::generate::GenRng;
+    use rand::Rng;
+    use crate::generate::{answers_path, gen_input_binary_seeded, gen_input_seeded, gen_input_with_answers, Distribution};
+    use crate::{haversine_approx_epsilon, test_samples};
+
+    #[test]
+    fn test_check_answers() {
+        let json = tempfile::NamedTempFile::new().unwrap();
+        let json_path = json.path().to_str().unwrap();
+        let answers = answers_path(json_path);
+
+        gen_input_with_answers(json_path, Distribution::Cluster(4), 1000, 42).unwrap();
+        let checked = check_answers(json_path, &answers);
+        std::fs::remove_file(&answers).unwrap();
+
+        assert!(checked.unwrap());
+    }
+
+    #[test]
+    fn test_answer_error() {
+        const SAMPLES: u64 = 1000;
+
+        let json = tempfile::NamedTempFile::new().unwrap();
+        let json_path = json.path().to_str().unwrap();
+        let answers = answers_path(json_path);
+
+        let expected = gen_input_with_answers(json_path, Distribution::Uniform, SAMPLES, 5).unwrap();
+        let (_, average, error) = average_haversine_with_answers(json_path, &answers).unwrap();
+        assert_eq!(average, expected);
+        assert_eq!((error.max, error.mean), (0.0, 0.0));
+
+        // Knock one pair's answer off by a kilometer
+        let mut bytes = std::fs::read(&answers).unwrap();
+        let corrupted = &mut bytes[10 * 8..11 * 8];
+        let answer = f64::from_le_bytes(corrupted.try_into().unwrap());
+        corrupted.copy_from_slice(&(answer + 1.0).to_le_bytes());
+        std::fs::write(&answers, bytes).unwrap();
+
+        let (_, _, error) = average_haversine_with_answers(json_path, &answers).unwrap();
+        std::fs::remove_file(&answers).unwrap();
+
+        assert!((error.max - 1.0).abs() < 1e-9, "{error:?}");
+        assert!((error.mean - 1.0 / SAMPLES as f64).abs() < 1e-9, "{error:?}");
+    }
+
+    #[test]
+    fn test_binary_matches_json() {
+        const SAMPLES: u64 = 1000;
+        const SEED: u64 = 1234;
+
+        for distribution in [Distribution::Uniform, Distribution::Cluster(4)] {
+            let json = tempfile::NamedTempFile::new().unwrap();
+            let binary = tempfile::NamedTempFile::new().unwrap();
+            let json_path = json.path().to_str().unwrap();
+            let binary_path = binary.path().to_str().unwrap();
+
+            let json_expected = gen_input_seeded(json_path, distribution, SAMPLES, SEED).unwrap();
+            let binary_expected = gen_input_binary_seeded(binary_path, distribution, SAMPLES, SEED).unwrap();
+            assert_eq!(json_expected, binary_expected);
+
+            let (size, binary_actual) = average_haversine_binary(binary_path).unwrap();
+            assert_eq!(size, 8 + SAMPLES as usize * 32);
+
+            assert_eq!(binary_actual, binary_expected);
+            assert_eq!(binary_actual, average_haversine(json_path).unwrap().1);
+        }
+    }
+
+    #[test]
+    fn test_binary_corrupt_count() {
+        // 2^59 pairs of 32 bytes wraps around to exactly the 0 bytes that follow
+        let binary = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(binary.path(), (1u64 << 59).to_le_bytes()).unwrap();
+
+        let err = average_haversine_binary(binary.path().to_str().unwrap()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_streaming_matches_tree() {
+        for distribution in [Distribution::Uniform, Distribution::Cluster(4)] {
+            let json = tempfile::NamedTempFile::new().unwrap();
+            let path = json.path().to_str().unwrap();
+
+            let expected = gen_input_seeded(path, distribution, 1000, 99).unwrap();
+            let (tree_size, tree) = average_haversine(path).unwrap();
+            let (streaming_size, streaming) = average_haversine_streaming(path).unwrap();
+
+            assert_eq!(streaming_size, tree_size);
+            assert_eq!(streaming, tree);
+            assert_eq!(streaming, expected);
+        }
+    }
+
+    #[test]
+    fn test_batch_matches_scalar() {
+        // Odd, so the last pair takes the scalar path
+        const PAIRS: usize = 10_001;
+
+        let mut rng = GenRng::seed_from_u64(7);
+        let mut coords = |lb: f64, ub: f64| (0..PAIRS).map(|_| rng.random_range(lb..ub)).collect::<Vec<_>>();
+        let (xs0, ys0) = (coords(-180.0, 180.0), coords(-90.0, 90.0));
+        let (xs1, ys1) = (coords(-180.0, 180.0), coords(-90.0, 90.0));
+
+        let scalar = (0..PAIRS).map(|i| haversine(xs0[i], ys0[i], xs1[i], ys1[i])).sum::<f64>() / PAIRS as f64;
+        let batch = haversine_batch(&xs0, &ys0, &xs1, &ys1) / PAIRS as f64;
+
+        assert!((scalar - batch).abs() < 1e-9, "scalar {scalar}, batch {batch}");
+
+        // Antipodes sit right at the edge of asin's domain
+        assert!(haversine_batch(&[0.0, 10.0], &[0.0, 20.0], &[180.0, -170.0], &[0.0, -20.0]).is_finite());
+    }
+
+    #[test]
+    fn test_approx_error() {
+        const PAIRS: usize = 1_000_000;
+
+        let mut rng = GenRng::seed_from_u64(3);
+        let mut total_error = 0.0;
+        for _ in 0..PAIRS {
+            let x0 = rng.random_range(-180.0..180.0);
+            let y0 = rng.random_range(-90.0..90.0);
+            let x1 = rng.random_range(-180.0..180.0);
+            let y1 = rng.random_range(-90.0..90.0);
+
+            total_error += (haversine_approx(x0, y0, x1, y1) - haversine(x0, y0, x1, y1)).abs();
+        }
+
+        let mean_error = total_error / PAIRS as f64;
+        assert!(mean_error < haversine_approx_epsilon(), "mean error {mean_error}");
+    }
+
+    #[test]
+    fn test_sum_matches_scalar() {
+        const PAIRS: usize = 10_001;
+
+        let mut rng = GenRng::seed_from_u64(11);
+        let pairs: Vec<[f64; 4]> = (0..PAIRS)
+            .map(|_| {
+                [
+                    rng.random_range(-180.0..180.0),
+                    rng.random_range(-90.0..90.0),
+                    rng.random_range(-180.0..180.0),
+                    rng.random_range(-90.0..90.0),
+                ]
+            })
+            .collect();
+
+        let scalar = pairs.iter().map(|&[x0, y0, x1, y1]| haversine(x0, y0, x1, y1)).sum::<f64>() / PAIRS as f64;
+        let sum = haversine_sum(&pairs) / PAIRS as f64;
+
+        assert!((scalar - sum).abs() < 1e-9, "scalar {scalar}, sum {sum}");
+        assert_eq!(haversine_sum(&[]), 0.0);
+    }
 
     #[test]
     fn test_uniform() {
-        test_samples(false, 1);
-        test_samples(false, 1000);
+        test_samples(Distribution::Uniform, 1);
+        test_samples(Distribution::Uniform, 1000);
     }
 
     #[test]
     fn test_cluster() {
-        test_samples(true, 1);
-        test_samples(true, 1000);
+        test_samples(Distribution::Cluster(4), 1);
+        test_samples(Distribution::Cluster(4), 1000);
     }
 
     #[test]
     fn test_large() {
-        test_samples(false, 10_000_000);
-        test_samples(true, 10_000_000);
+        test_samples(Distribution::Uniform, 10_000_000);
+        test_samples(Distribution::Cluster(4), 10_000_000);
     }
 }
@@ -7,74 +7,291 @@ const X_UB: f64 = 180.0;
 const Y_LB: f64 = -90.0;
 const Y_UB: f64 = 90.0;
 
-use rand::Rng;
+use rand::{Rng, RngCore};
 
-use crate::EARTH_RADIUS;
+use crate::{parse::write_json_pair, EARTH_RADIUS};
 
-pub fn gen_input(outpath: &str, uniform: bool, samples: u64) -> io::Result<f64> {
+/// SplitMix64, used instead of the rand crate's generators since its whole state is a single
+/// u64 that can be snapshotted and resumed
+#[derive(Debug, Clone)]
+pub struct GenRng {
+    state: u64,
+}
 
-    let outfile = std::fs::File::create(outpath)?;
-    let mut writer = BufWriter::new(outfile);
+impl GenRng {
+    pub fn seed_from_u64(seed: u64) -> Self {
+        Self { state: seed }
+    }
+}
+
+impl RngCore for GenRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        for chunk in dst.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
 
-    let mut rng = rand::rng();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Distribution {
+    /// Every point drawn from the whole globe
+    Uniform,
+    /// Points drawn from the given number of randomly placed boxes, `samples / clusters`
+    /// consecutive points from each
+    Cluster(u32),
+}
+
+pub const SNAPSHOT_SIZE: usize = 7 * size_of::<u64>();
+
+/// Writes the haversine input JSON, and can be snapshotted partway through so a large file can
+/// be regenerated from near a given sample without replaying everything before it
+#[derive(Debug, Clone)]
+pub struct Generator {
+    rng: GenRng,
+    seed: u64,
+    distribution: Distribution,
+    samples: u64,
+    next_sample: u64,
+    /// `[xa, xb, ya, yb]` for each cluster, with each taking an equal run of consecutive samples
+    bounds: Vec<[f64; 4]>,
+    sum: f64,
+}
 
-    writeln!(&mut writer, "{{")?;
-    writeln!(&mut writer, "    \"pairs\": [")?;
+impl Generator {
+    pub fn new(distribution: Distribution, samples: u64, seed: u64) -> Self {
+        let mut rng = GenRng::seed_from_u64(seed);
 
-    let mut xa;
-    let mut xb;
-    let mut ya;
-    let mut yb;
+        let bounds = match distribution {
+            Distribution::Uniform => vec![[X_LB, X_UB, Y_LB, Y_UB]],
+            Distribution::Cluster(clusters) => (0..clusters.max(1))
+                .map(|_| {
+                    let mut xa = rng.random_range(X_LB..X_UB);
+                    let mut xb = rng.random_range(X_LB..X_UB);
 
-    if uniform {
-        xa = X_LB;
-        xb = X_UB;
+                    if xa > xb {
+                        (xa, xb) = (xb, xa)
+                    }
 
-        ya = Y_LB;
-        yb = Y_UB;
+                    let mut ya = rng.random_range(Y_LB..Y_UB);
+                    let mut yb = rng.random_range(Y_LB..Y_UB);
 
-    } else {
-        xa = rng.random_range(X_LB..X_UB);
-        xb = rng.random_range(X_LB..X_UB);
+                    if ya > yb {
+                        (ya, yb) = (yb, ya)
+                    }
 
-        if xa > xb {
-            (xa, xb) = (xb, xa)
+                    [xa, xb, ya, yb]
+                })
+                .collect(),
+        };
+
+        Self {
+            rng,
+            seed,
+            distribution,
+            samples,
+            next_sample: 0,
+            bounds,
+            sum: 0.0,
         }
+    }
+
+    pub fn write_header<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{{")?;
+        writeln!(writer, "    \"pairs\": [")
+    }
+
+    pub fn write_footer<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "    ]")?;
+        writeln!(writer, "}}")
+    }
+
+    fn next_pair(&mut self, sample: u64) -> ([f64; 4], f64) {
+        let cluster = (sample as u128 * self.bounds.len() as u128 / self.samples as u128) as usize;
+        let [xa, xb, ya, yb] = self.bounds[cluster];
 
-        ya = rng.random_range(Y_LB..Y_UB);
-        yb = rng.random_range(Y_LB..Y_UB);
+        let x0 = self.rng.random_range(xa..xb);
+        let x1 = self.rng.random_range(xa..xb);
 
-        if ya > yb {
-            (ya, yb) = (yb, ya)
+        let y0 = self.rng.random_range(ya..yb);
+        let y1 = self.rng.random_range(ya..yb);
+
+        let distance = reference_haversine(x0, y0, x1, y1);
+        self.sum += distance;
+
+        ([x0, y0, x1, y1], distance)
+    }
+
+    /// Writes up to `count` more pairs, stopping early at the total sample count
+    pub fn write_samples<W: Write>(&mut self, writer: &mut W, count: u64) -> io::Result<()> {
+        self.write_samples_with_answers(writer, &mut io::sink(), count)
+    }
+
+    /// Like `write_samples`, also writing each pair's reference distance to `answers` as a
+    /// little-endian f64
+    pub fn write_samples_with_answers<W: Write, A: Write>(
+        &mut self,
+        writer: &mut W,
+        answers: &mut A,
+        count: u64,
+    ) -> io::Result<()> {
+        // Reused across pairs so generating doesn't allocate per sample
+        let mut pair_json = String::new();
+
+        let end = self.samples.min(self.next_sample + count);
+        for sample in self.next_sample..end {
+            let (pair, distance) = self.next_pair(sample);
+
+            pair_json.clear();
+            write_json_pair(&mut pair_json, pair).expect("Writing to a String can't fail");
+            write!(writer, "      {pair_json}")?;
+            answers.write_all(&distance.to_le_bytes())?;
+
+            if sample < self.samples - 1 {
+                writeln!(writer, ",")?;
+            } else {
+                writeln!(writer)?;
+            }
         }
+
+        self.next_sample = end;
+        Ok(())
     }
 
-    let mut sum = 0.0;
-    for sample in 0..samples {
-        let x0 = rng.random_range(xa..xb);
-        let x1 = rng.random_range(xa..xb);
+    /// Writes up to `count` more pairs as little-endian `[x0, y0, x1, y1]` f64s
+    pub fn write_samples_binary<W: Write>(&mut self, writer: &mut W, count: u64) -> io::Result<()> {
+        let end = self.samples.min(self.next_sample + count);
+        for sample in self.next_sample..end {
+            for coord in self.next_pair(sample).0 {
+                writer.write_all(&coord.to_le_bytes())?;
+            }
+        }
 
+        self.next_sample = end;
+        Ok(())
+    }
 
-        let y0 = rng.random_range(ya..yb);
-        let y1 = rng.random_range(ya..yb);
+    /// Reference average over every sample written so far
+    pub fn average(&self) -> f64 {
+        self.sum / self.next_sample as f64
+    }
 
-        write!(writer, "      {{\"x0\": {x0}, \"y0\": {y0}, \"x1\": {x1}, \"y1\": {y1}}}")?;
+    pub fn snapshot(&self) -> [u8; SNAPSHOT_SIZE] {
+        // The cluster boxes aren't stored, since they're regenerated from the seed
+        let words = [
+            self.rng.state,
+            self.seed,
+            (self.distribution == Distribution::Uniform) as u64,
+            self.bounds.len() as u64,
+            self.samples,
+            self.next_sample,
+            self.sum.to_bits(),
+        ];
 
-        if sample < samples - 1 {
-            writeln!(writer, ",")?;
-        } else {
-            writeln!(writer)?;
+        let mut snapshot = [0; SNAPSHOT_SIZE];
+        for (chunk, word) in snapshot.chunks_exact_mut(8).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
         }
 
-        sum += reference_haversine(x0, y0, x1, y1);
+        snapshot
     }
 
-    writeln!(&mut writer, "    ]")?;
-    writeln!(&mut writer, "}}")?;
+    pub fn resume(snapshot: &[u8; SNAPSHOT_SIZE]) -> Self {
+        let mut words = snapshot
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()));
+        let mut next = || words.next().unwrap();
+
+        let state = next();
+        let seed = next();
+        let uniform = next() != 0;
+        let clusters = next() as u32;
+        let samples = next();
+        let distribution = if uniform {
+            Distribution::Uniform
+        } else {
+            Distribution::Cluster(clusters)
+        };
+
+        Self {
+            rng: GenRng { state },
+            next_sample: next(),
+            sum: f64::from_bits(next()),
+            ..Self::new(distribution, samples, seed)
+        }
+    }
+}
+
+pub fn gen_input(outpath: &str, distribution: Distribution, samples: u64) -> io::Result<f64> {
+    gen_input_seeded(outpath, distribution, samples, rand::random())
+}
+
+pub fn gen_input_seeded(outpath: &str, distribution: Distribution, samples: u64, seed: u64) -> io::Result<f64> {
+
+    let outfile = std::fs::File::create(outpath)?;
+    let mut writer = BufWriter::new(outfile);
+
+    let mut generator = Generator::new(distribution, samples, seed);
+
+    generator.write_header(&mut writer)?;
+    generator.write_samples(&mut writer, samples)?;
+    generator.write_footer(&mut writer)?;
+
+    Ok(generator.average())
+}
 
-    Ok(sum / samples as f64)
+pub fn answers_path(outpath: &str) -> String {
+    format!("{outpath}.answers")
 }
 
+/// Like `gen_input_seeded`, also writing `<outpath>.answers` with every pair's reference distance
+/// followed by the average, all as little-endian f64s
+pub fn gen_input_with_answers(outpath: &str, distribution: Distribution, samples: u64, seed: u64) -> io::Result<f64> {
+    let mut writer = BufWriter::new(std::fs::File::create(outpath)?);
+    let mut answers = BufWriter::new(std::fs::File::create(answers_path(outpath))?);
+
+    let mut generator = Generator::new(distribution, samples, seed);
+
+    generator.write_header(&mut writer)?;
+    generator.write_samples_with_answers(&mut writer, &mut answers, samples)?;
+    generator.write_footer(&mut writer)?;
+
+    let average = generator.average();
+    answers.write_all(&average.to_le_bytes())?;
+
+    Ok(average)
+}
+
+pub fn gen_input_binary(outpath: &str, distribution: Distribution, samples: u64) -> io::Result<f64> {
+    gen_input_binary_seeded(outpath, distribution, samples, rand::random())
+}
+
+/// Writes the sample count as a little-endian u64, followed by each pair as four little-endian
+/// f64s. Produces the same pairs and average as `gen_input_seeded` for the same seed.
+pub fn gen_input_binary_seeded(outpath: &str, distribution: Distribution, samples: u64, seed: u64) -> io::Result<f64> {
+    let outfile = std::fs::File::create(outpath)?;
+    let mut writer = BufWriter::new(outfile);
+
+    let mut generator = Generator::new(distribution, samples, seed);
+
+    writer.write_all(&samples.to_le_bytes())?;
+    generator.write_samples_binary(&mut writer, samples)?;
+
+    Ok(generator.average())
+}
 
 fn reference_haversine(x0: f64, y0: f64, x1: f64, y1: f64) -> f64 {
 
@@ -93,3 +310,161 @@ fn reference_haversine(x0: f64, y0: f64, x1: f64, y1: f64) -> f64 {
 
     c * EARTH_RADIUS
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::JsonValue;
+
+    #[test]
+    fn test_resume_from_snapshot() {
+        const SAMPLES: u64 = 1001;
+        const SEED: u64 = 0xdeadbeef;
+
+        for distribution in [Distribution::Uniform, Distribution::Cluster(3)] {
+            let mut single_shot = Vec::new();
+            let mut generator = Generator::new(distribution, SAMPLES, SEED);
+            generator.write_header(&mut single_shot).unwrap();
+            generator.write_samples(&mut single_shot, SAMPLES).unwrap();
+            generator.write_footer(&mut single_shot).unwrap();
+            let expected = generator.average();
+
+            let mut resumed = Vec::new();
+            let mut first_half = Generator::new(distribution, SAMPLES, SEED);
+            first_half.write_header(&mut resumed).unwrap();
+            first_half.write_samples(&mut resumed, SAMPLES / 2).unwrap();
+            let snapshot = first_half.snapshot();
+            drop(first_half);
+
+            let mut second_half = Generator::resume(&snapshot);
+            second_half.write_samples(&mut resumed, SAMPLES).unwrap();
+            second_half.write_footer(&mut resumed).unwrap();
+
+            assert_eq!(single_shot, resumed);
+            assert_eq!(expected, second_half.average());
+        }
+    }
+
+    #[test]
+    fn test_same_seed_same_file() {
+        let first = tempfile::NamedTempFile::new().unwrap();
+        let second = tempfile::NamedTempFile::new().unwrap();
+        let third = tempfile::NamedTempFile::new().unwrap();
+        let path = |file: &tempfile::NamedTempFile| file.path().to_str().unwrap().to_string();
+
+        let avg = gen_input_seeded(&path(&first), Distribution::Cluster(4), 1000, 7).unwrap();
+        assert_eq!(gen_input_seeded(&path(&second), Distribution::Cluster(4), 1000, 7).unwrap(), avg);
+        gen_input_seeded(&path(&third), Distribution::Cluster(4), 1000, 8).unwrap();
+
+        let read = |file| std::fs::read(path(file)).unwrap();
+        assert_eq!(read(&first), read(&second));
+        assert_ne!(read(&first), read(&third));
+    }
+
+    #[test]
+    fn test_seeded_golden() {
+        // Pinned output, so a change to the generator or the RNG shows up as a failure rather than
+        // silently producing different inputs for the same seed
+        let cases = [
+            (
+                Distribution::Uniform,
+                9993.886707055726,
+                r#"      {"x0":86.96335635785636,"y0":-39.85179655407506,"x1":-122.43225856430877,"y1":-28.045671025745243},"#,
+            ),
+            (
+                Distribution::Cluster(4),
+                5726.553461331849,
+                r#"      {"x0":-100.74426779415464,"y0":-38.74877792715801,"x1":-18.67701234804028,"y1":-31.718009199216517},"#,
+            ),
+        ];
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+
+        for (distribution, golden_average, golden_first_pair) in cases {
+            let average = gen_input_seeded(path, distribution, 1000, 42).unwrap();
+            let text = std::fs::read_to_string(path).unwrap();
+
+            assert_eq!(text.lines().nth(2).unwrap(), golden_first_pair);
+            // The coordinates come from the RNG alone, but the distances go through libm
+            assert!(crate::haversine_close(golden_average, average, 1e-12), "{average}");
+        }
+    }
+
+    #[test]
+    fn test_answers_file() {
+        const SAMPLES: u64 = 1000;
+
+        let json = tempfile::NamedTempFile::new().unwrap();
+        let json_path = json.path().to_str().unwrap();
+        let answers = answers_path(json_path);
+
+        let average = gen_input_with_answers(json_path, Distribution::Uniform, SAMPLES, 3).unwrap();
+        let bytes = std::fs::read(&answers).unwrap();
+        std::fs::remove_file(&answers).unwrap();
+
+        let values: Vec<f64> = bytes
+            .chunks_exact(size_of::<f64>())
+            .map(|c| f64::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+
+        assert_eq!(bytes.len(), values.len() * size_of::<f64>());
+        assert_eq!(values.len() as u64, SAMPLES + 1);
+        assert_eq!(*values.last().unwrap(), average);
+
+        // The same pairs as without the answers file
+        assert_eq!(gen_input_seeded(json_path, Distribution::Uniform, SAMPLES, 3).unwrap(), average);
+        let sum: f64 = values[..SAMPLES as usize].iter().sum();
+        assert_eq!(sum / SAMPLES as f64, average);
+    }
+
+    #[test]
+    fn test_clusters_average() {
+        const SAMPLES: u64 = 10_000;
+
+        let mut generator = Generator::new(Distribution::Cluster(4), SAMPLES, 99);
+        assert_eq!(generator.bounds.len(), 4);
+
+        generator.write_samples(&mut io::sink(), SAMPLES).unwrap();
+        let average = generator.average();
+
+        // No two points on the globe are further apart than half its circumference
+        let max_distance = std::f64::consts::PI * EARTH_RADIUS;
+        assert!(average.is_finite());
+        assert!(average > 0.0 && average <= max_distance, "{average}");
+    }
+
+    #[test]
+    fn test_clusters_distinct_ranges() {
+        const SAMPLES: u64 = 4000;
+        const CLUSTERS: u32 = 4;
+
+        let mut generator = Generator::new(Distribution::Cluster(CLUSTERS), SAMPLES, 1234);
+        let mut out = Vec::new();
+        generator.write_header(&mut out).unwrap();
+        generator.write_samples(&mut out, SAMPLES).unwrap();
+        generator.write_footer(&mut out).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        let json = JsonValue::parse(&text);
+        let pairs = json["pairs"].elements();
+
+        let runs: Vec<_> = pairs.chunks(pairs.len() / CLUSTERS as usize).collect();
+        assert_eq!(runs.len(), CLUSTERS as usize);
+
+        // Each run of pairs stays inside the box of the cluster it was drawn from
+        for (run, &[xa, xb, ya, yb]) in runs.iter().zip(&generator.bounds) {
+            for pair in *run {
+                for (x, y) in [("x0", "y0"), ("x1", "y1")] {
+                    let (x, y) = (f64::from(&pair[x]), f64::from(&pair[y]));
+                    assert!((xa..=xb).contains(&x), "x {x} outside [{xa}, {xb}]");
+                    assert!((ya..=yb).contains(&y), "y {y} outside [{ya}, {yb}]");
+                }
+            }
+        }
+
+        for (i, a) in generator.bounds.iter().enumerate() {
+            assert!(generator.bounds[i + 1..].iter().all(|b| a != b), "{:?}", generator.bounds);
+        }
+    }
+}
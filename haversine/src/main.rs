@@ -1,67 +1,150 @@
-use core::panic;
-use std::{io, time::Duration};
+use std::io;
 
-use profiler::metrics::{cpu_time, cpu_to_duration};
+use profiler::{
+    clear_profiler,
+    metrics::{cpu_time, cpu_to_duration},
+    profile_report,
+};
+
+use calc::{average_haversine_counted, check_answers};
+use generate::{answers_path, gen_input_seeded, gen_input_with_answers, Distribution};
+use math::StdMath;
 
 pub mod calc;
 pub mod generate;
+pub mod math;
 pub mod parse;
 // #[cfg(test)]
 pub mod cpu_profiling;
 pub mod repetition_tester;
+pub mod simd;
 pub mod util;
 
 #[cfg(feature = "mmap_alloc")]
 pub mod allocator;
+#[cfg(feature = "arena_alloc")]
+pub mod arena;
+
+#[cfg(all(feature = "mmap_alloc", feature = "arena_alloc"))]
+compile_error!("mmap_alloc and arena_alloc both replace the global allocator, enable only one");
 
 pub use util::*;
 
-fn main() -> io::Result<()> {
-    // let start = cpu_time();
-    // let mut uniform = true;
-    // let mut samples: Option<u64> = None;
-    //
-    // for arg in std::env::args().skip(1) {
-    //     match arg.as_str() {
-    //         "-u" | "--uniform" => uniform = true,
-    //         "-c" | "--cluster" => uniform = false,
-    //         _ => {
-    //             if let Ok(n) = arg.parse() {
-    //                 samples = Some(n)
-    //             } else {
-    //                 panic!("Bad args");
-    //             }
-    //         }
-    //     }
-    // }
-    // let samples = samples.unwrap();
-    //
-    // test_samples(uniform, samples);
-    //
-    // println!(
-    //     "Total time elapsed: {:09.4}ms",
-    //     cpu_to_duration(cpu_time() - start).as_secs_f64() * 1_000.0
-    // );
-    // Ok(())
-    //
-    //
-    
-
-    // const TO_WRITE: u64 = 1024 * 1024;
-    // let mut tester = RepetitionTester::new(Duration::from_secs(5), TO_WRITE);
-    // while tester.run_new_trial() {
-    //     let mut data = vec![0; TO_WRITE as usize];
-    //
-    //     tester.start_trial_timer();
-    //     for i in 0..data.len() {
-    //         data[i] = i
-    //     }
-    //     tester.end_trial_timer();
-    //
-    //     tester.count_bytes(data.len() as u64);
-    // };
-
-    cpu_profiling::profile_cache_sizes();
+const USAGE: &str = "\
+usage: haversine generate (--uniform | --cluster [--clusters N]) --samples N --out PATH [--seed S] [--answers]
+       haversine compute PATH [--answers]
+       haversine cache-sizes";
+
+/// Clusters used by `--cluster` when `--clusters` isn't given
+const DEFAULT_CLUSTERS: u32 = 4;
+
+fn usage_error(msg: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{msg}\n\n{USAGE}"))
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, args: &mut impl Iterator<Item = String>) -> io::Result<T> {
+    let value = args
+        .next()
+        .ok_or_else(|| usage_error(format!("Expected a value after {flag}")))?;
+
+    value
+        .parse()
+        .map_err(|_| usage_error(format!("Invalid value '{value}' for {flag}")))
+}
+
+/// Runs the command in `args`, not including the program name
+pub fn run(mut args: impl Iterator<Item = String>) -> io::Result<()> {
+    let start = cpu_time();
+
+    match args.next().as_deref() {
+        Some("generate") => generate(args)?,
+        Some("compute") => compute(args)?,
+        Some("cache-sizes") => cpu_profiling::profile_cache_sizes(),
+        Some(cmd) => return Err(usage_error(format!("Unknown command '{cmd}'"))),
+        None => return Err(usage_error("Expected a command")),
+    }
+
+    println!(
+        "Total time elapsed: {:09.4}ms",
+        cpu_to_duration(cpu_time() - start).as_secs_f64() * 1_000.0
+    );
+
+    Ok(())
+}
+
+fn generate(mut args: impl Iterator<Item = String>) -> io::Result<()> {
+    let mut uniform = None;
+    let mut clusters = DEFAULT_CLUSTERS;
+    let mut samples = None;
+    let mut outpath = None;
+    let mut seed = rand::random();
+    let mut answers = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-u" | "--uniform" => uniform = Some(true),
+            "-c" | "--cluster" => uniform = Some(false),
+            "--clusters" => clusters = parse_value(&arg, &mut args)?,
+            "--samples" => samples = Some(parse_value(&arg, &mut args)?),
+            "--out" => outpath = Some(parse_value::<String>(&arg, &mut args)?),
+            "--seed" => seed = parse_value(&arg, &mut args)?,
+            "--answers" => answers = true,
+            _ => return Err(usage_error(format!("Unknown argument '{arg}'"))),
+        }
+    }
+
+    let distribution = match uniform {
+        Some(true) => Distribution::Uniform,
+        Some(false) => Distribution::Cluster(clusters),
+        None => return Err(usage_error("Expected --uniform or --cluster")),
+    };
+    let samples = samples.ok_or_else(|| usage_error("Expected --samples"))?;
+    let outpath = outpath.ok_or_else(|| usage_error("Expected --out"))?;
+
+    let average = if answers {
+        gen_input_with_answers(&outpath, distribution, samples, seed)?
+    } else {
+        gen_input_seeded(&outpath, distribution, samples, seed)?
+    };
+
+    println!("Method: {distribution:?}");
+    println!("Seed: {seed}");
+    println!("Pair count: {samples}");
+    println!("Expected avg: {average}");
+
+    Ok(())
+}
+
+fn compute(mut args: impl Iterator<Item = String>) -> io::Result<()> {
+    let mut path = None;
+    let mut answers = false;
+
+    for arg in args.by_ref() {
+        match arg.as_str() {
+            "--answers" => answers = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(usage_error(format!("Unknown argument '{arg}'"))),
+        }
+    }
+    let path = path.ok_or_else(|| usage_error("Expected an input path"))?;
+
+    clear_profiler();
+    let (input_size, pairs, average) = average_haversine_counted::<StdMath>(&path)?;
+
+    println!("Input size: {input_size}");
+    println!("Pair count: {pairs}");
+    println!("Haversine avg: {average}");
+
+    if answers {
+        let matched = check_answers(&path, &answers_path(&path))?;
+        println!("Validation: {}", if matched { "PASS" } else { "FAIL" });
+    }
+
+    profile_report();
 
     Ok(())
 }
+
+fn main() -> io::Result<()> {
+    run(std::env::args().skip(1))
+}
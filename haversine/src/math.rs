@@ -0,0 +1,137 @@
+//! The transcendentals the haversine needs, behind a trait so approximations can be swapped in at
+//! the call site and profiled against std. `generate` always uses std directly, so answers stay
+//! exact to validate against.
+
+use std::f64::consts::{FRAC_PI_2, PI};
+
+pub trait HaversineMath {
+    fn sin(x: f64) -> f64;
+    fn cos(x: f64) -> f64;
+    fn asin(x: f64) -> f64;
+    fn sqrt(x: f64) -> f64;
+}
+
+/// Straight through to std, the reference
+pub struct StdMath;
+
+impl HaversineMath for StdMath {
+    fn sin(x: f64) -> f64 {
+        x.sin()
+    }
+
+    fn cos(x: f64) -> f64 {
+        x.cos()
+    }
+
+    fn asin(x: f64) -> f64 {
+        x.asin()
+    }
+
+    fn sqrt(x: f64) -> f64 {
+        x.sqrt()
+    }
+}
+
+/// Range-reduced Taylor series, within 1e-15 of std over the ranges the haversine uses: sin on
+/// [-pi, pi], cos on [-pi/2, pi/2] and asin on [0, 1]. Square roots are a single instruction
+/// already, so sqrt is left to std.
+pub struct PolyMath;
+
+impl HaversineMath for PolyMath {
+    fn sin(x: f64) -> f64 {
+        // sin(x) = sin(pi - x) folds [-pi, pi] into [-pi/2, pi/2], where the series converges fast
+        let x = if x > FRAC_PI_2 {
+            PI - x
+        } else if x < -FRAC_PI_2 {
+            -PI - x
+        } else {
+            x
+        };
+
+        x * poly(x * x, &SIN_COEFFS)
+    }
+
+    fn cos(x: f64) -> f64 {
+        poly(x * x, &COS_COEFFS)
+    }
+
+    fn asin(x: f64) -> f64 {
+        // asin(x) = pi/2 - 2 asin(sqrt((1 - x) / 2)) keeps the series argument at most 1/2
+        if x > 0.5 {
+            let z = ((1.0 - x) * 0.5).sqrt();
+            FRAC_PI_2 - 2.0 * z * poly(z * z, &ASIN_COEFFS)
+        } else {
+            x * poly(x * x, &ASIN_COEFFS)
+        }
+    }
+
+    fn sqrt(x: f64) -> f64 {
+        x.sqrt()
+    }
+}
+
+/// Evaluates `coeffs[0] + coeffs[1] x + coeffs[2] x^2 + ...` by Horner's rule
+fn poly(x: f64, coeffs: &[f64]) -> f64 {
+    coeffs.iter().rev().fold(0.0, |acc, c| acc * x + c)
+}
+
+/// Taylor series of sin(x) / x in x^2: (-1)^k / (2k + 1)!
+pub(crate) const SIN_COEFFS: [f64; 10] = {
+    let mut coeffs = [1.0; 10];
+    let mut k = 1;
+    while k < coeffs.len() {
+        coeffs[k] = -coeffs[k - 1] / ((2 * k) * (2 * k + 1)) as f64;
+        k += 1;
+    }
+    coeffs
+};
+
+/// Taylor series of cos(x) in x^2: (-1)^k / (2k)!
+pub(crate) const COS_COEFFS: [f64; 11] = {
+    let mut coeffs = [1.0; 11];
+    let mut k = 1;
+    while k < coeffs.len() {
+        coeffs[k] = -coeffs[k - 1] / ((2 * k - 1) * (2 * k)) as f64;
+        k += 1;
+    }
+    coeffs
+};
+
+/// Taylor series of asin(x) / x in x^2: (2k)! / (4^k (k!)^2 (2k + 1))
+pub(crate) const ASIN_COEFFS: [f64; 24] = {
+    let mut coeffs = [1.0; 24];
+    // (2k)! / (4^k (k!)^2), built up alongside
+    let mut central = 1.0;
+    let mut k = 1;
+    while k < coeffs.len() {
+        central = central * (2 * k - 1) as f64 / (2 * k) as f64;
+        coeffs[k] = central / (2 * k + 1) as f64;
+        k += 1;
+    }
+    coeffs
+};
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    use super::{HaversineMath, PolyMath, StdMath};
+
+    /// Max absolute difference between `approx` and `exact` over evenly spaced points in [lo, hi]
+    fn max_error(lo: f64, hi: f64, approx: fn(f64) -> f64, exact: fn(f64) -> f64) -> f64 {
+        const STEPS: usize = 1_000_000;
+
+        (0..=STEPS)
+            .map(|i| lo + (hi - lo) * i as f64 / STEPS as f64)
+            .map(|x| (approx(x) - exact(x)).abs())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn test_poly_math_error() {
+        assert!(max_error(-PI, PI, PolyMath::sin, StdMath::sin) < 1e-15);
+        assert!(max_error(-FRAC_PI_2, FRAC_PI_2, PolyMath::cos, StdMath::cos) < 1e-15);
+        assert!(max_error(0.0, 1.0, PolyMath::asin, StdMath::asin) < 1e-15);
+        assert!(max_error(0.0, 1.0, PolyMath::sqrt, StdMath::sqrt) == 0.0);
+    }
+}
@@ -1,4 +1,4 @@
-use std::str;
+use std::{fmt, str};
 
 use profiler_macro::instrument;
 
@@ -6,12 +6,17 @@ use profiler_macro::instrument;
 pub enum JsonValue<'a> {
     Object{ pairs: Vec<(&'a str, JsonValue<'a>)> },
     Array{ elements: Vec<JsonValue<'a>> },
+    /// The text between the quotes as it appears in the source, escapes included, so parsing
+    /// never has to copy
     String(&'a str),
     Number(f64),
     Boolean(bool),
     Null,
 }
 
+/// How deeply arrays and objects may nest in `JsonValue::try_parse`
+pub const DEFAULT_MAX_DEPTH: usize = 1024;
+
 #[derive(Debug, PartialEq)]
 enum JsonToken<'a> {
     CurlyStart,
@@ -25,15 +30,52 @@ enum JsonToken<'a> {
     Null,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonError {
+    /// Numbers that aren't valid JSON, like a lone `-`, `.5` or `+5`
+    InvalidNumber(String),
+    UnterminatedString,
+    UnexpectedToken(String),
+    /// The input ended partway through a value, or before any value at all
+    UnexpectedEof,
+    /// Arrays and objects nested deeper than the given limit
+    DepthExceeded(usize),
+    /// An object closed without a key the caller needed
+    MissingKey(String),
+}
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonError::InvalidNumber(num) => write!(f, "Invalid JSON number '{num}'"),
+            JsonError::UnterminatedString => write!(f, "Expected closing quote for JSON string"),
+            JsonError::UnexpectedToken(token) => write!(f, "Unexpected JSON token '{token}'"),
+            JsonError::UnexpectedEof => write!(f, "Unexpected end of JSON input"),
+            JsonError::DepthExceeded(max) => write!(f, "JSON nested deeper than {max} levels"),
+            JsonError::MissingKey(key) => write!(f, "JSON object has no '{key}' key"),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+fn unexpected(data: &[u8]) -> JsonError {
+    JsonError::UnexpectedToken(data.iter().take(25).map(|x| *x as char).collect())
+}
+
 impl<'a> JsonToken<'a> {
-    fn parse_token(data: &'a [u8]) -> (Self, usize) {
+    fn parse_token(data: &'a [u8]) -> Result<(Self, usize), JsonError> {
         let mut ptr = 0;
 
-        while data[ptr].is_ascii_whitespace() || data[ptr] == b',' {
+        while ptr < data.len() && (data[ptr].is_ascii_whitespace() || data[ptr] == b',') {
             ptr += 1 ;
         }
 
-        match data[ptr] {
+        if ptr == data.len() {
+            return Err(JsonError::UnexpectedEof);
+        }
+
+        let res = match data[ptr] {
             b'{' => (JsonToken::CurlyStart, ptr + 1),
             b'}' => (JsonToken::CurlyEnd, ptr + 1),
 
@@ -43,128 +85,459 @@ impl<'a> JsonToken<'a> {
             b':' => (JsonToken::Colon, ptr + 1),
 
             b'"' => {
-                let size = data[ptr + 1..].iter().position(|x| *x == b'"').expect("Expected closing quote for JSON string");
+                // Escaped characters are skipped over, so `\"` doesn't end the string
+                let mut size = 0;
+                loop {
+                    match data.get(ptr + 1 + size) {
+                        Some(b'"') => break,
+                        Some(b'\\') => size += 2,
+                        Some(_) => size += 1,
+                        None => return Err(JsonError::UnterminatedString),
+                    }
+                }
 
                 let s = unsafe { str::from_utf8_unchecked(&data[ptr + 1..ptr + 1 + size]) };
                 (JsonToken::String(s), ptr + 2 + size)
             }
-            x if x.is_ascii_digit() || x == b'-' => {
+            b'0'..=b'9' | b'-' | b'+' | b'.' => {
+                let digits = |from: usize| data[from..].iter().take_while(|x| x.is_ascii_digit()).count();
+
                 let mut num_size = 0;
+                let mut valid = true;
+                // Missing digits only mean truncated input if the data ran out where they belong
+                let mut truncated = false;
+                let mut expect_digits = |num_size: usize, count: usize| {
+                    if valid && count == 0 {
+                        valid = false;
+                        truncated = ptr + num_size == data.len();
+                    }
+                };
 
-                if x == b'-' {
+                if data[ptr] == b'-' {
                     num_size += 1;
                 }
 
-                num_size += data[ptr + num_size..].iter().take_while(|x| x.is_ascii_digit()).count();
+                // JSON requires digits on both sides of the decimal point
+                let int_digits = digits(ptr + num_size);
+                expect_digits(num_size, int_digits);
+                num_size += int_digits;
 
                 if data.len() > ptr + num_size && data[ptr + num_size] == b'.' {
                     num_size += 1;
-                    num_size += data[ptr + num_size..].iter().take_while(|x| x.is_ascii_digit()).count();
+
+                    let frac_digits = digits(ptr + num_size);
+                    expect_digits(num_size, frac_digits);
+                    num_size += frac_digits;
                 }
 
+                if data.len() > ptr + num_size && matches!(data[ptr + num_size], b'e' | b'E') {
+                    num_size += 1;
+
+                    if data.len() > ptr + num_size && matches!(data[ptr + num_size], b'+' | b'-') {
+                        num_size += 1;
+                    }
+
+                    let exp_digits = digits(ptr + num_size);
+                    expect_digits(num_size, exp_digits);
+                    num_size += exp_digits;
+                }
+
+                // A leading '+' isn't consumed above, so grab it for the error message
+                let num_size = num_size.max(1);
                 let num_str = unsafe {
                     str::from_utf8_unchecked(&data[ptr..ptr + num_size])
                 };
-                let num = num_str.parse().unwrap_or_else(|_| panic!("Couldn't parse '{num_str}' as f64"));
 
-                (JsonToken::Number(num), ptr + num_size)
-            }
-            b't' => {
-                if data[ptr..ptr + 4] == *b"true" {
-                    (JsonToken::Boolean(true), ptr + 4)
-                } else {
-                    panic!("Expected JSON token starting with 't' to be 'true'");
+                if truncated {
+                    return Err(JsonError::UnexpectedEof);
                 }
-            },
-
-            b'f' => {
-                if data[ptr..ptr + 5] == *b"false" {
-                    (JsonToken::Boolean(false), ptr + 5)
-                } else {
-                    panic!("Expected JSON token starting with 'f' to be 'false'");
+                if !valid {
+                    return Err(JsonError::InvalidNumber(num_str.to_string()));
                 }
-            }
 
-            b'n' => {
-                if data[ptr..ptr + 4] == *b"null" {
-                    (JsonToken::Null, ptr + 4)
-                } else {
-                    panic!("Expected JSON token starting with 'n' to be 'null'");
-                }
+                let num = num_str
+                    .parse()
+                    .map_err(|_| JsonError::InvalidNumber(num_str.to_string()))?;
+
+                (JsonToken::Number(num), ptr + num_size)
             }
-            _ => panic!("Unexpected JSON token '{}...'", data[ptr..].iter().take(25).map(|x| *x as char).collect::<String>()),
-        }
+            b't' if data[ptr..].starts_with(b"true") => (JsonToken::Boolean(true), ptr + 4),
+            b'f' if data[ptr..].starts_with(b"false") => (JsonToken::Boolean(false), ptr + 5),
+            b'n' if data[ptr..].starts_with(b"null") => (JsonToken::Null, ptr + 4),
+            b't' if b"true".starts_with(&data[ptr..]) => return Err(JsonError::UnexpectedEof),
+            b'f' if b"false".starts_with(&data[ptr..]) => return Err(JsonError::UnexpectedEof),
+            b'n' if b"null".starts_with(&data[ptr..]) => return Err(JsonError::UnexpectedEof),
+
+            _ => return Err(unexpected(&data[ptr..])),
+        };
+
+        Ok(res)
     }
 }
 
 impl<'a> JsonValue<'a> {
-    #[instrument]
+    /// Panics on invalid JSON, see `try_parse`
     pub fn parse(data: &'a str) -> Self {
-        Self::parse_rec(data.as_bytes()).0
+        Self::try_parse(data).unwrap_or_else(|err| panic!("{err}"))
     }
 
-    fn parse_rec(data: &'a [u8]) -> (Self, &'a[u8]) {
-        let (token, ptr) = JsonToken::parse_token(data);
-        let mut data = &data[ptr..];
-        
-        let res = match token {
-            JsonToken::CurlyStart => {
-                let mut pairs = Vec::new();
-                loop {
-                    let (curr, ptr) = JsonToken::parse_token(data);
-                    data = &data[ptr..];
+    #[instrument("parse", bytes = data.len())]
+    pub fn try_parse(data: &'a str) -> Result<Self, JsonError> {
+        Self::try_parse_with_max_depth(data, DEFAULT_MAX_DEPTH)
+    }
 
-                    let key = match curr {
-                        JsonToken::String(s) => s,
-                        JsonToken::CurlyEnd => break,
-                        _ => panic!("Found non-string object key!")
-                    };
+    /// Parsing itself never recurses, but dropping, comparing or printing a value does, so
+    /// nesting is limited to keep those from overflowing the stack
+    pub fn try_parse_with_max_depth(data: &'a str, max_depth: usize) -> Result<Self, JsonError> {
+        // Some tools export files with a UTF-8 BOM, which isn't JSON whitespace
+        let data = data.strip_prefix('\u{feff}').unwrap_or(data);
 
-                    let (curr, ptr) = JsonToken::parse_token(data);
-                    data = &data[ptr..];
+        Ok(Self::parse_value(data.as_bytes(), max_depth)?.0)
+    }
 
-                    assert_eq!(curr, JsonToken::Colon, "Expected colon between kv pair");
-                    
-                    let (val, d) = Self::parse_rec(data);
-                    data = d;
+    /// How many arrays and objects deep the most nested value is, so scalars have depth 0
+    pub fn depth(&self) -> usize {
+        match self {
+            JsonValue::Object { pairs } => 1 + pairs.iter().map(|(_, v)| v.depth()).max().unwrap_or(0),
+            JsonValue::Array { elements } => 1 + elements.iter().map(|v| v.depth()).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
 
-                    pairs.push((key, val));
-                };
+    /// Total number of values, counting this one and every array and object along the way
+    pub fn node_count(&self) -> usize {
+        match self {
+            JsonValue::Object { pairs } => 1 + pairs.iter().map(|(_, v)| v.node_count()).sum::<usize>(),
+            JsonValue::Array { elements } => 1 + elements.iter().map(|v| v.node_count()).sum::<usize>(),
+            _ => 1,
+        }
+    }
+
+    /// Serializes to JSON, putting each element on its own line indented by `indent` spaces per
+    /// level when given, or with no whitespace at all otherwise
+    pub fn to_json_string(&self, indent: Option<usize>) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out, indent, 0).expect("Writing to a String can't fail");
+        out
+    }
 
+    fn write_json<W: fmt::Write>(&self, w: &mut W, indent: Option<usize>, level: usize) -> fmt::Result {
+        let newline = |w: &mut W, level: usize| match indent {
+            Some(indent) => write!(w, "\n{:1$}", "", indent * level),
+            None => Ok(()),
+        };
 
-                JsonValue::Object { pairs }
-            },
-            JsonToken::SquareStart => {
-                let mut elements = Vec::new();
-                loop {
-                    let (curr, ptr) = JsonToken::parse_token(data);
-                    if curr == JsonToken::SquareEnd {
-                        data = &data[ptr..];
-                        break;
+        match self {
+            JsonValue::Object { pairs } => {
+                w.write_char('{')?;
+                for (i, (key, val)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        w.write_char(',')?;
+                    }
+                    newline(w, level + 1)?;
+                    write_json_string(w, key)?;
+                    w.write_str(if indent.is_some() { ": " } else { ":" })?;
+                    val.write_json(w, indent, level + 1)?;
+                }
+                if !pairs.is_empty() {
+                    newline(w, level)?;
+                }
+                w.write_char('}')
+            }
+            JsonValue::Array { elements } => {
+                w.write_char('[')?;
+                for (i, val) in elements.iter().enumerate() {
+                    if i > 0 {
+                        w.write_char(',')?;
                     }
+                    newline(w, level + 1)?;
+                    val.write_json(w, indent, level + 1)?;
+                }
+                if !elements.is_empty() {
+                    newline(w, level)?;
+                }
+                w.write_char(']')
+            }
+            JsonValue::String(s) => write_json_string(w, s),
+            // JSON has no representation for NaN or the infinities
+            JsonValue::Number(n) => write_json_number(w, *n),
+            JsonValue::Boolean(b) => write!(w, "{b}"),
+            JsonValue::Null => w.write_str("null"),
+        }
+    }
+
+    /// Parses iteratively with an explicit stack of open containers, so deep nesting can't
+    /// overflow the call stack
+    fn parse_value(mut data: &'a [u8], max_depth: usize) -> Result<(Self, &'a [u8]), JsonError> {
+        let mut stack: Vec<Container<'a>> = Vec::new();
+
+        loop {
+            let (token, ptr) = JsonToken::parse_token(data)?;
+            data = &data[ptr..];
 
-                    let (element, d) = Self::parse_rec(data);
+            let opens = matches!(token, JsonToken::CurlyStart | JsonToken::SquareStart);
+            if opens && stack.len() >= max_depth {
+                return Err(JsonError::DepthExceeded(max_depth));
+            }
+
+            let mut value = match token {
+                JsonToken::CurlyStart => {
+                    let (key, d) = Self::parse_key(data)?;
                     data = d;
 
-                    elements.push(element);
-                };
+                    match key {
+                        Some(key) => {
+                            stack.push(Container::Object { pairs: Vec::new(), key });
+                            continue;
+                        }
+                        None => JsonValue::Object { pairs: Vec::new() },
+                    }
+                }
+                JsonToken::SquareStart => {
+                    stack.push(Container::Array { elements: Vec::new() });
+                    continue;
+                }
+                JsonToken::SquareEnd if matches!(stack.last(), Some(Container::Array { .. })) => {
+                    let Some(Container::Array { elements }) = stack.pop() else {
+                        unreachable!()
+                    };
+                    JsonValue::Array { elements }
+                }
+                JsonToken::Number(n) => JsonValue::Number(n),
+                JsonToken::String(s) => JsonValue::String(s),
+                JsonToken::Boolean(b) => JsonValue::Boolean(b),
+                JsonToken::Null => JsonValue::Null,
+                _ => return Err(JsonError::UnexpectedToken(format!("{token:?}"))),
+            };
+
+            // Hand the finished value to its parent, closing any objects it completes
+            loop {
+                match stack.last_mut() {
+                    None => return Ok((value, data)),
+                    Some(Container::Array { elements }) => {
+                        elements.push(value);
+                        break;
+                    }
+                    Some(Container::Object { pairs, key }) => {
+                        pairs.push((key, value));
+
+                        let (next, d) = Self::parse_key(data)?;
+                        data = d;
+
+                        if let Some(next) = next {
+                            *key = next;
+                            break;
+                        }
 
-                JsonValue::Array { elements }
-            },
-            JsonToken::Number(n) => JsonValue::Number(n),
-            JsonToken::String(s) => JsonValue::String(s),
-            JsonToken::Boolean(b) => JsonValue::Boolean(b),
-            JsonToken::Null => JsonValue::Null,
-            _ => panic!("Unexpected token {token:?}"),
+                        let Some(Container::Object { pairs, .. }) = stack.pop() else {
+                            unreachable!()
+                        };
+                        value = JsonValue::Object { pairs };
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads an object key and the colon after it, or `None` if the object closes instead
+    fn parse_key(data: &'a [u8]) -> Result<(Option<&'a str>, &'a [u8]), JsonError> {
+        let (token, ptr) = JsonToken::parse_token(data)?;
+        let key = match token {
+            JsonToken::String(s) => s,
+            JsonToken::CurlyEnd => return Ok((None, &data[ptr..])),
+            _ => return Err(unexpected(data.trim_ascii_start())),
         };
 
-        (res, data)
+        let data = &data[ptr..];
+        let (token, ptr) = JsonToken::parse_token(data)?;
+        if token != JsonToken::Colon {
+            return Err(unexpected(data.trim_ascii_start()));
+        }
+
+        Ok((Some(key), &data[ptr..]))
+    }
+}
+
+/// Reads the `pairs` array of a haversine input straight off the token stream, yielding
+/// `(x0, y0, x1, y1)` for each pair without building any `JsonValue` nodes. Other top-level keys
+/// before `pairs` are parsed and thrown away, and anything after the array is ignored.
+pub struct PairStream<'a> {
+    data: &'a [u8],
+    started: bool,
+    done: bool,
+}
+
+impl<'a> PairStream<'a> {
+    pub fn new(data: &'a str) -> Self {
+        let data = data.strip_prefix('\u{feff}').unwrap_or(data);
+
+        Self { data: data.as_bytes(), started: false, done: false }
+    }
+
+    fn next_token(&mut self) -> Result<JsonToken<'a>, JsonError> {
+        let (token, ptr) = JsonToken::parse_token(self.data)?;
+        self.data = &self.data[ptr..];
+
+        Ok(token)
+    }
+
+    fn expect_token(&mut self, expected: JsonToken) -> Result<(), JsonError> {
+        match self.next_token()? {
+            token if token == expected => Ok(()),
+            token => Err(JsonError::UnexpectedToken(format!("{token:?}"))),
+        }
+    }
+
+    /// Returns `None` once the enclosing object closes
+    fn next_key(&mut self) -> Result<Option<&'a str>, JsonError> {
+        let (key, data) = JsonValue::parse_key(self.data)?;
+        self.data = data;
+
+        Ok(key)
+    }
+
+    /// Moves past the opening bracket of the top-level `pairs` array
+    fn find_pairs(&mut self) -> Result<(), JsonError> {
+        self.expect_token(JsonToken::CurlyStart)?;
+
+        loop {
+            match self.next_key()? {
+                Some("pairs") => break,
+                Some(_) => self.data = JsonValue::parse_value(self.data, DEFAULT_MAX_DEPTH)?.1,
+                None => return Err(JsonError::MissingKey("pairs".to_string())),
+            }
+        }
+
+        self.expect_token(JsonToken::SquareStart)
+    }
+
+    fn next_pair(&mut self) -> Result<Option<(f64, f64, f64, f64)>, JsonError> {
+        if !self.started {
+            self.started = true;
+            self.find_pairs()?;
+        }
+
+        match self.next_token()? {
+            JsonToken::SquareEnd => return Ok(None),
+            JsonToken::CurlyStart => {}
+            token => return Err(JsonError::UnexpectedToken(format!("{token:?}"))),
+        }
+
+        let mut coords = [None; 4];
+
+        while let Some(key) = self.next_key()? {
+            let slot = PAIR_KEYS
+                .iter()
+                .position(|k| *k == key)
+                .ok_or_else(|| JsonError::UnexpectedToken(key.to_string()))?;
+
+            match self.next_token()? {
+                JsonToken::Number(n) => coords[slot] = Some(n),
+                token => return Err(JsonError::UnexpectedToken(format!("{token:?}"))),
+            }
+        }
+
+        match coords {
+            [Some(x0), Some(y0), Some(x1), Some(y1)] => Ok(Some((x0, y0, x1, y1))),
+            _ => {
+                let missing = PAIR_KEYS.iter().zip(coords).find(|(_, c)| c.is_none()).unwrap().0;
+                Err(JsonError::MissingKey(missing.to_string()))
+            }
+        }
+    }
+}
+
+impl Iterator for PairStream<'_> {
+    type Item = Result<(f64, f64, f64, f64), JsonError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let pair = self.next_pair().transpose();
+        self.done = !matches!(pair, Some(Ok(_)));
+
+        pair
     }
 }
 
+/// `PairStream` as `[x0, y0, x1, y1]` quadruples, panicking on invalid input like
+/// `JsonValue::parse`
+pub fn parse_pairs(data: &str) -> impl Iterator<Item = [f64; 4]> + '_ {
+    PairStream::new(data).map(|pair| {
+        let (x0, y0, x1, y1) = pair.unwrap_or_else(|err| panic!("{err}"));
+        [x0, y0, x1, y1]
+    })
+}
+
+/// An array or object that's still being parsed
+enum Container<'a> {
+    /// `key` is waiting on its value
+    Object { pairs: Vec<(&'a str, JsonValue<'a>)>, key: &'a str },
+    Array { elements: Vec<JsonValue<'a>> },
+}
+
+/// Compact JSON, see `to_json_string` for indented output
+impl fmt::Display for JsonValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_json(f, None, 0)
+    }
+}
+
+/// The keys of a pair object, in the order the generator writes them
+const PAIR_KEYS: [&str; 4] = ["x0", "y0", "x1", "y1"];
+
+/// Writes `[x0, y0, x1, y1]` as the compact object `JsonValue` would, without building one
+pub(crate) fn write_json_pair<W: fmt::Write>(w: &mut W, pair: [f64; 4]) -> fmt::Result {
+    w.write_char('{')?;
+    for (i, (key, n)) in PAIR_KEYS.into_iter().zip(pair).enumerate() {
+        if i > 0 {
+            w.write_char(',')?;
+        }
+        write_json_string(w, key)?;
+        w.write_char(':')?;
+        write_json_number(w, n)?;
+    }
+    w.write_char('}')
+}
+
+fn write_json_number<W: fmt::Write>(w: &mut W, n: f64) -> fmt::Result {
+    // JSON has no representation for NaN or the infinities
+    if n.is_finite() {
+        write!(w, "{n}")
+    } else {
+        w.write_str("null")
+    }
+}
+
+/// Strings hold JSON text, so escapes that are already valid are kept as they are, which lets
+/// parsed strings round-trip unchanged. Anything else that can't appear raw is escaped.
+fn write_json_string<W: fmt::Write>(w: &mut W, s: &str) -> fmt::Result {
+    w.write_char('"')?;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some('"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't' | 'u')) => {
+                w.write_char(c)?;
+                w.write_char(chars.next().unwrap())?;
+            }
+            '"' => w.write_str("\\\"")?,
+            '\\' => w.write_str("\\\\")?,
+            '\n' => w.write_str("\\n")?,
+            '\r' => w.write_str("\\r")?,
+            '\t' => w.write_str("\\t")?,
+            c if c.is_control() => write!(w, "\\u{:04x}", c as u32)?,
+            c => w.write_char(c)?,
+        }
+    }
+    w.write_char('"')
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::generate::{Distribution, Generator};
     use JsonValue::*;
 
     #[test]
@@ -192,6 +565,131 @@ mod tests {
         assert_eq!(JsonValue::parse("-3.2415"), Number(-3.2415));
     }
 
+    #[test]
+    fn test_parse_exponent() {
+        for num in ["1e5", "-2.5e-3", "3E+2", "6.022E23", "1.5e-10", "0e0"] {
+            assert_eq!(JsonValue::parse(num), Number(num.parse().unwrap()), "{num}");
+        }
+
+        let arr = Array { elements: vec![Number(1e5), Number(-2.5e-3)] };
+        assert_eq!(JsonValue::parse("[1e5, -2.5e-3]"), arr);
+
+        assert_eq!(JsonValue::try_parse("1e"), Err(JsonError::UnexpectedEof));
+        assert_eq!(JsonValue::try_parse("1e+"), Err(JsonError::UnexpectedEof));
+        assert_eq!(JsonValue::try_parse("[1e]"), Err(JsonError::InvalidNumber("1e".to_string())));
+    }
+
+    #[test]
+    fn test_parse_invalid_num() {
+        let invalid = |num: &str| Err(JsonError::InvalidNumber(num.to_string()));
+
+        assert_eq!(JsonValue::try_parse("-]"), invalid("-"));
+        assert_eq!(JsonValue::try_parse("."), invalid("."));
+        assert_eq!(JsonValue::try_parse("+5"), invalid("+"));
+        assert_eq!(JsonValue::try_parse(".5"), invalid(".5"));
+        assert_eq!(JsonValue::try_parse("-.5"), invalid("-.5"));
+        assert_eq!(JsonValue::try_parse("[1, 2.]"), invalid("2."));
+
+        assert_eq!(JsonValue::try_parse("-0.5"), Ok(Number(-0.5)));
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        assert_eq!(JsonValue::try_parse("tru"), Err(JsonError::UnexpectedEof));
+        assert_eq!(JsonValue::try_parse("-"), Err(JsonError::UnexpectedEof));
+        assert_eq!(JsonValue::try_parse("   "), Err(JsonError::UnexpectedEof));
+        assert_eq!(JsonValue::try_parse(""), Err(JsonError::UnexpectedEof));
+        assert_eq!(JsonValue::try_parse("[1, 2.5"), Err(JsonError::UnexpectedEof));
+        assert_eq!(JsonValue::try_parse("{\"a\": fals"), Err(JsonError::UnexpectedEof));
+        assert_eq!(JsonValue::try_parse("[1, 2."), Err(JsonError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_parse_deep_nesting() {
+        const DEPTH: usize = 100_000;
+
+        let arrays = "[".repeat(DEPTH) + &"]".repeat(DEPTH);
+        assert_eq!(JsonValue::try_parse(&arrays), Err(JsonError::DepthExceeded(DEFAULT_MAX_DEPTH)));
+
+        let objects = "{\"a\": ".repeat(DEPTH) + "1" + &"}".repeat(DEPTH);
+        assert_eq!(JsonValue::try_parse(&objects), Err(JsonError::DepthExceeded(DEFAULT_MAX_DEPTH)));
+
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert_eq!(JsonValue::try_parse_with_max_depth(&nested(5), 5).map(|v| v.depth()), Ok(5));
+        assert_eq!(JsonValue::try_parse_with_max_depth(&nested(6), 5), Err(JsonError::DepthExceeded(5)));
+        assert_eq!(JsonValue::try_parse_with_max_depth("{}", 0), Err(JsonError::DepthExceeded(0)));
+        assert_eq!(JsonValue::try_parse_with_max_depth("1", 0), Ok(Number(1.0)));
+    }
+
+    #[test]
+    fn test_parse_mismatched_brackets() {
+        assert!(JsonValue::try_parse("[1, 2}").is_err());
+        assert!(JsonValue::try_parse("{\"a\": 1]").is_err());
+        assert!(JsonValue::try_parse("{\"a\" 1}").is_err());
+        assert!(JsonValue::try_parse("]").is_err());
+        assert_eq!(JsonValue::parse("[[], {}, [[]]]").depth(), 3);
+    }
+
+    #[test]
+    fn test_pair_stream() {
+        let json = r#"{"count": 2, "meta": {"seed": [1, 2]}, "pairs": [
+            {"x0": 1.5, "y0": -2, "x1": 3, "y1": 4},
+            {"y1": 8, "x1": 7, "y0": 6, "x0": 5}
+        ], "trailing": true}"#;
+
+        let pairs: Result<Vec<_>, _> = PairStream::new(json).collect();
+        assert_eq!(pairs, Ok(vec![(1.5, -2.0, 3.0, 4.0), (5.0, 6.0, 7.0, 8.0)]));
+
+        assert_eq!(PairStream::new(r#"{"pairs": []}"#).count(), 0);
+    }
+
+    #[test]
+    fn test_parse_pairs_matches_tree() {
+        let mut out = Vec::new();
+        let mut generator = Generator::new(Distribution::Cluster(4), 1000, 17);
+        generator.write_header(&mut out).unwrap();
+        generator.write_samples(&mut out, 1000).unwrap();
+        generator.write_footer(&mut out).unwrap();
+        let data = std::string::String::from_utf8(out).unwrap();
+
+        let json = JsonValue::parse(&data);
+        let tree: Vec<[f64; 4]> = json["pairs"]
+            .elements()
+            .iter()
+            .map(|pair| ["x0", "y0", "x1", "y1"].map(|key| f64::from(&pair[key])))
+            .collect();
+
+        assert_eq!(parse_pairs(&data).collect::<Vec<_>>(), tree);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_pairs_invalid() {
+        parse_pairs(r#"{"pairs": [{"x0": 1}]}"#).for_each(drop);
+    }
+
+    #[test]
+    fn test_pair_stream_errors() {
+        let first = |json: &str| PairStream::new(json).next();
+
+        assert_eq!(first(r#"{"count": 0}"#), Some(Err(JsonError::MissingKey("pairs".to_string()))));
+        assert_eq!(
+            first(r#"{"pairs": [{"x0": 1, "y0": 2, "y1": 4}]}"#),
+            Some(Err(JsonError::MissingKey("x1".to_string())))
+        );
+        assert_eq!(
+            first(r#"{"pairs": [{"x0": 1, "z0": 2}]}"#),
+            Some(Err(JsonError::UnexpectedToken("z0".to_string())))
+        );
+        assert_eq!(first(r#"{"pairs": [{"x0": 1, "y0""#), Some(Err(JsonError::UnexpectedEof)));
+        assert!(matches!(first(r#"{"pairs": {}}"#), Some(Err(JsonError::UnexpectedToken(_)))));
+
+        // Iteration stops at the first error
+        let mut stream = PairStream::new(r#"{"pairs": [{"x0": true}, {}]}"#);
+        assert!(matches!(stream.next(), Some(Err(_))));
+        assert_eq!(stream.next(), None);
+    }
+
     #[test]
     fn test_parse_array() {
         let arr = Array { elements: vec![Null, Boolean(true), Number(1.2), String("hello")] };
@@ -250,4 +748,147 @@ mod tests {
 
         assert_eq!(JsonValue::parse(json), expected);
     }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let json = r#"{
+            "name": "Bob",
+            "age": 24.5,
+            "happy": true,
+            "wife": null,
+            "cars": [
+                { "size": "big", "wheels": [1, -2, 3e5] },
+                { "size": "smallish", "wheels": [] }
+            ],
+            "pets": {}
+        }"#;
+
+        let parsed = JsonValue::parse(json);
+        for indent in [None, Some(4)] {
+            let serialized = parsed.to_json_string(indent);
+            println!("{serialized}");
+            assert_eq!(JsonValue::parse(&serialized), parsed);
+        }
+
+        assert_eq!(parsed.to_string(), parsed.to_json_string(None));
+
+        // Compact, so serializing gives back exactly the same text
+        let escaped = r#"{"path\\":"C:\\dir\\","quote":"say \"hi\" \u0041\n"}"#;
+        let parsed = JsonValue::parse(escaped);
+        assert_eq!(parsed["quote"], String(r#"say \"hi\" \u0041\n"#));
+        assert_eq!(parsed.to_json_string(None), escaped);
+        assert_eq!(JsonValue::parse(&parsed.to_json_string(Some(2))), parsed);
+        assert_eq!(
+            JsonValue::parse("[1, {\"a\": [true]}, []]").to_json_string(Some(2)),
+            "[\n  1,\n  {\n    \"a\": [\n      true\n    ]\n  },\n  []\n]"
+        );
+    }
+
+    #[test]
+    fn test_serialize_escapes() {
+        assert_eq!(String("say \"hi\"\\\n\t\u{1}").to_string(), r#""say \"hi\"\\\n\t\u0001""#);
+        assert_eq!(Array { elements: vec![Number(f64::NAN), Number(-0.5)] }.to_string(), "[null,-0.5]");
+    }
+
+    #[test]
+    fn test_write_json_pair() {
+        let mut out = std::string::String::new();
+        write_json_pair(&mut out, [1.5, -2.0, 180.0, f64::INFINITY]).unwrap();
+        assert_eq!(out, r#"{"x0":1.5,"y0":-2,"x1":180,"y1":null}"#);
+    }
+
+    #[test]
+    fn test_depth_and_node_count() {
+        let json = r#"{
+            "name": "Bob",
+            "age": 24,
+            "happy": true,
+            "cars": [
+                {
+                    "size": "big"
+                },
+                {
+                    "size": "smallish"
+                }
+            ]
+        }"#;
+
+        let parsed = JsonValue::parse(json);
+        assert_eq!(parsed.depth(), 3);
+        assert_eq!(parsed.node_count(), 9);
+
+        assert_eq!(Null.depth(), 0);
+        assert_eq!(Null.node_count(), 1);
+        assert_eq!(JsonValue::parse("[]").depth(), 1);
+        assert_eq!(JsonValue::parse("[]").node_count(), 1);
+    }
+
+    #[test]
+    fn test_parse_bom() {
+        let json = "\u{feff}{\"name\": \"Bob\", \"age\": 24}";
+
+        let expected = Object { pairs: vec![
+            ("name", String("Bob")),
+            ("age", Number(24.0)),
+        ] };
+
+        assert_eq!(JsonValue::parse(json), expected);
+    }
+
+    #[test]
+    fn test_parse_trailing_whitespace() {
+        assert_eq!(JsonValue::parse("  null \n"), Null);
+        assert_eq!(JsonValue::parse("-3.5\n\n"), Number(-3.5));
+        assert_eq!(JsonValue::parse("[1, 2]\r\n\t "), Array { elements: vec![Number(1.0), Number(2.0)] });
+    }
+
+    #[test]
+    fn test_parse_profiler_disabled() {
+        use profiler::{profile_snapshot, set_profiler_enabled};
+
+        let json = r#"{"pairs": [{"x0": 1.5, "y0": -2, "x1": 3, "y1": 4.25}], "ok": true}"#;
+
+        let instrumented = JsonValue::parse(json);
+
+        set_profiler_enabled(false);
+        let uninstrumented = JsonValue::parse(json);
+        set_profiler_enabled(true);
+
+        assert_eq!(instrumented, uninstrumented);
+
+        let calls = profile_snapshot().nodes.iter().filter(|n| n.name == "parse").map(|n| n.calls).sum::<u64>();
+        assert_eq!(calls, if cfg!(feature = "profile") { 1 } else { 0 });
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn test_parse_chrome_trace() {
+        use profiler::{clear_profiler, report_chrome_trace};
+        use profiler_macro::instr;
+
+        clear_profiler();
+        for i in 0..3 {
+            instr!("Outer", {
+                instr!("Inner", {
+                    JsonValue::parse(&format!("[{i}]"));
+                });
+            });
+        }
+
+        let trace = report_chrome_trace();
+        let json = JsonValue::parse(&trace);
+        let events = json["traceEvents"].elements();
+
+        // Every instr! and JsonValue::parse call is its own complete event
+        assert_eq!(events.len(), 9);
+        for event in events {
+            assert_eq!(<&str>::from(&event["ph"]), "X");
+            assert!(f64::from(&event["dur"]) >= 0.0);
+        }
+
+        let count = |name| events.iter().filter(|e| <&str>::from(&e["name"]) == name).count();
+        assert_eq!(count("Outer"), 3);
+        assert_eq!(count("Inner"), 3);
+        assert_eq!(count("parse"), 3);
+    }
 }
@@ -19,12 +19,39 @@ pub struct TestResults {
     pub total: Metrics,
 }
 
+/// A snapshot of a run, with the min and max trial alongside averages over every trial
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrialSummary {
+    pub trial_count: usize,
+    pub min_cycles: i64,
+    pub max_cycles: i64,
+    pub avg_cycles: f64,
+    /// Throughput of the fastest trial
+    pub best_bytes_per_sec: f64,
+    pub avg_bytes_per_sec: f64,
+    pub avg_pagefaults: f64,
+}
+
+type NewMinCallback = Box<dyn FnMut(&Metrics)>;
+
 pub struct RepetitionTester {
     end_time: u64,
+    test_dur: Duration,
+    // While set, trials run unrecorded until the CPU timer reaches it
+    warmup_end: Option<u64>,
+    warmup_trials: u32,
     expected_bytes_processed: u64,
     curr: Metrics,
+    // The last trial that was recorded, which is what the byte count is checked against once the
+    // window closes
+    last_trial: Option<Metrics>,
     pub results: TestResults,
     state: TesterState,
+    csv_writer: Option<Box<dyn Write>>,
+    // Every trial's time_elapsed, in the order they ran until a percentile query sorts them
+    trial_times: Vec<i64>,
+    new_min_callback: Option<NewMinCallback>,
+    last_was_new_min: bool,
 }
 
 #[derive(PartialEq)]
@@ -97,14 +124,164 @@ impl RepetitionTester {
     pub fn new(test_dur: Duration, expected_bytes_processed: u64) -> Self {
         Self {
             end_time: cpu_time() + duration_to_cpu(test_dur),
+            test_dur,
+            warmup_end: None,
+            warmup_trials: 0,
             expected_bytes_processed,
             curr: Metrics::default(),
+            last_trial: None,
             results: TestResults::new(),
             state: TesterState::NotStarted,
+            csv_writer: None,
+            trial_times: Vec::new(),
+            new_min_callback: None,
+            last_was_new_min: false,
+        }
+    }
+
+    /// Starts a fresh `test_dur` window, discarding every recorded result. The CSV writer and
+    /// new-min callback are kept, as is the capacity of the per-trial storage.
+    pub fn reset(&mut self, test_dur: Duration, expected_bytes_processed: u64) {
+        self.end_time = cpu_time() + duration_to_cpu(test_dur);
+        self.test_dur = test_dur;
+        self.warmup_end = None;
+        self.warmup_trials = 0;
+        self.expected_bytes_processed = expected_bytes_processed;
+        self.curr = Metrics::default();
+        self.last_trial = None;
+        self.results = TestResults::new();
+        self.state = TesterState::NotStarted;
+        self.trial_times.clear();
+        self.last_was_new_min = false;
+    }
+
+    /// Runs trials without recording them until `warmup` has passed, then starts the `test_dur`
+    /// window, so cold caches and first-touch page faults don't skew the results
+    pub fn with_warmup(mut self, warmup: Duration) -> Self {
+        self.warmup_end = Some(cpu_time() + duration_to_cpu(warmup));
+        self
+    }
+
+    /// How many trials ran during the warmup without being recorded
+    pub fn warmup_trials(&self) -> u32 {
+        self.warmup_trials
+    }
+
+    /// Called with the trial's metrics whenever a trial beats the fastest time seen so far
+    pub fn with_new_min_callback(mut self, callback: impl FnMut(&Metrics) + 'static) -> Self {
+        self.new_min_callback = Some(Box::new(callback));
+        self
+    }
+
+    /// Whether the most recently completed trial beat the fastest time seen before it
+    pub fn last_was_new_min(&self) -> bool {
+        self.last_was_new_min
+    }
+
+    pub fn results(&self) -> &TestResults {
+        &self.results
+    }
+
+    /// `None` until a trial has completed
+    pub fn summary(&self) -> Option<TrialSummary> {
+        let trial_count = self.trial_times.len();
+        if trial_count == 0 {
+            return None;
+        }
+
+        let per_sec = |bytes: f64, cycles: f64| {
+            let secs = cpu_to_duration(cycles as u64).as_secs_f64();
+            if secs > 0.0 { bytes / secs } else { 0.0 }
+        };
+
+        let TestResults { min, max, total } = &self.results;
+        let avg_cycles = total.time_elapsed as f64 / trial_count as f64;
+        let avg_bytes = total.bytes_processed as f64 / trial_count as f64;
+
+        Some(TrialSummary {
+            trial_count,
+            min_cycles: min.time_elapsed,
+            max_cycles: max.time_elapsed,
+            avg_cycles,
+            best_bytes_per_sec: per_sec(min.bytes_processed as f64, min.time_elapsed as f64),
+            avg_bytes_per_sec: per_sec(avg_bytes, avg_cycles),
+            avg_pagefaults: total.pagefaults as f64 / trial_count as f64,
+        })
+    }
+
+    /// The time_elapsed (in CPU timer ticks) that `p` percent of completed trials were at or
+    /// under, using the nearest-rank method. `None` until a trial has completed.
+    pub fn percentile(&mut self, p: f64) -> Option<i64> {
+        assert!((0.0..=100.0).contains(&p), "Percentile {p} is outside of 0..=100");
+
+        // Sorting here rather than per trial keeps recording cheap inside the measurement loop
+        if !self.trial_times.is_sorted() {
+            self.trial_times.sort_unstable();
+        }
+
+        let rank = ((p / 100.0) * self.trial_times.len() as f64).ceil() as usize;
+        self.trial_times.get(rank.saturating_sub(1)).copied()
+    }
+
+    /// Records every completed trial as a `time_elapsed,bytes_processed,pagefaults,gb_per_sec`
+    /// row, where `time_elapsed` is in CPU timer ticks
+    pub fn with_csv_writer(mut self, writer: impl Write + 'static) -> Self {
+        let mut writer = Box::new(writer);
+        writeln!(writer, "time_elapsed,bytes_processed,pagefaults,gb_per_sec")
+            .expect("Failed to write CSV header");
+
+        self.csv_writer = Some(writer);
+        self
+    }
+
+    fn write_csv_row(&mut self) {
+        let Some(writer) = self.csv_writer.as_mut() else {
+            return;
+        };
+
+        const GB: f64 = (1024 * 1024 * 1024) as f64;
+        let secs = cpu_to_duration(self.curr.time_elapsed as u64).as_secs_f64();
+
+        writeln!(
+            writer,
+            "{},{},{},{:.5}",
+            self.curr.time_elapsed,
+            self.curr.bytes_processed,
+            self.curr.pagefaults,
+            self.curr.bytes_processed as f64 / GB / secs
+        )
+        .expect("Failed to write CSV row");
+    }
+
+    fn check_bytes_processed(&self, trial: &Metrics) {
+        if self.expected_bytes_processed != trial.bytes_processed as u64 {
+            panic!(
+                "Trial finished with different number of bytes read ({}, expected {})",
+                trial.bytes_processed, self.expected_bytes_processed
+            );
         }
     }
 
     pub fn run_new_trial(&mut self) -> bool {
+        if let Some(warmup_end) = self.warmup_end {
+            if self.state == TesterState::Testing {
+                self.check_bytes_processed(&self.curr);
+                self.warmup_trials += 1;
+            }
+
+            self.curr = Metrics::default();
+            if cpu_time() < warmup_end {
+                self.state = TesterState::Testing;
+                return true;
+            }
+
+            self.warmup_end = None;
+            self.end_time = cpu_time() + duration_to_cpu(self.test_dur);
+            self.state = TesterState::NotStarted;
+        }
+
+        let mut new_min = false;
+
         if self.state == TesterState::Testing {
             self.results.total.bytes_processed += self.curr.bytes_processed;
             self.results.total.time_elapsed += self.curr.time_elapsed;
@@ -116,18 +293,31 @@ impl RepetitionTester {
 
             if self.curr.time_elapsed < self.results.min.time_elapsed {
                 self.results.min = self.curr.clone();
+                new_min = true;
+
+                if let Some(callback) = self.new_min_callback.as_mut() {
+                    callback(&self.curr);
+                }
             }
+
+            self.trial_times.push(self.curr.time_elapsed);
+
+            self.write_csv_row();
+            self.last_trial = Some(self.curr.clone());
         }
+        self.last_was_new_min = new_min;
 
         if cpu_time() >= self.end_time {
-            if self.expected_bytes_processed != self.curr.bytes_processed as u64 {
-                panic!(
-                    "Trial finished with different number of bytes read ({}, expected {})",
-                    self.curr.bytes_processed, self.expected_bytes_processed
-                );
+            // A window that closes before any trial ran has nothing to check
+            if let Some(last_trial) = &self.last_trial {
+                self.check_bytes_processed(last_trial);
             }
 
             self.state = TesterState::TrialCompleted;
+            if let Some(writer) = self.csv_writer.as_mut() {
+                writer.flush().expect("Failed to flush CSV output");
+            }
+
             print!("\r                                                                                          \r");
             self.results.min.print_result("Min");
             println!();
@@ -146,7 +336,14 @@ impl RepetitionTester {
                 print!("\r                                                                                     \r");
                 // print("Trial 1: Min time 0157.3855ms, 1064.356mb 6.60gb/s, PF: 68119 (15k/fault)");
                 print!("Trial {}: ", self.results.total.trial_count);
-                self.results.min.print_result("Min");
+
+                // Improvements get their own line so long runs keep a record of them
+                if new_min {
+                    self.results.min.print_result("New min");
+                    println!();
+                } else {
+                    self.results.min.print_result("Min");
+                }
             }
         }
 
@@ -174,7 +371,15 @@ impl RepetitionTester {
 
 #[cfg(test)]
 mod tests {
-    use crate::{generate::gen_input, read_to_string_fast};
+    use crate::{
+        calc::{haversine_approx, haversine_with},
+        generate::{gen_input, Distribution, GenRng},
+        math::StdMath,
+        parse::{parse_pairs, JsonValue},
+        read_to_string_fast,
+    };
+    use rand::Rng;
+    use profiler::set_profiler_enabled;
 
     #[cfg(feature = "mmap_alloc")]
     use crate::util::uninit_vec;
@@ -183,7 +388,7 @@ mod tests {
 
     use core::slice;
     use std::{
-        ffi::c_void, io::Read, os::unix::fs::MetadataExt, path::Path, ptr::null_mut, sync::Mutex,
+        cell::RefCell, ffi::c_void, io::{BufWriter, Read}, os::unix::fs::MetadataExt, path::Path, ptr::null_mut, rc::Rc, sync::Mutex,
     };
     static FILE_LOCK: Mutex<()> = Mutex::new(());
 
@@ -193,16 +398,19 @@ mod tests {
     fn get_file() -> String {
         let _lock = FILE_LOCK.lock().unwrap();
 
-        const UNIFORM: bool = false;
+        const DISTRIBUTION: Distribution = Distribution::Cluster(1);
 
         let path = format!(
             "inputs/test_input_{}_{}.f64",
             SAMPLES,
-            if UNIFORM { "uniform" } else { "cluster" }
+            match DISTRIBUTION {
+                Distribution::Uniform => "uniform",
+                Distribution::Cluster(_) => "cluster",
+            }
         );
 
         if !Path::new(&path).exists() {
-            gen_input(&path, UNIFORM, SAMPLES).expect("Failed to generate input");
+            gen_input(&path, DISTRIBUTION, SAMPLES).expect("Failed to generate input");
         }
 
         path
@@ -238,6 +446,317 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_csv_output() {
+        const BYTES: u64 = 1024;
+
+        let csv = tempfile::NamedTempFile::new().unwrap();
+        let mut tester = RepetitionTester::new(Duration::from_millis(50), BYTES)
+            .with_csv_writer(BufWriter::new(csv.reopen().unwrap()));
+
+        while tester.run_new_trial() {
+            tester.start_trial_timer();
+            let data = vec![1u8; BYTES as usize];
+            tester.end_trial_timer();
+
+            tester.count_bytes(data.len() as u64);
+        }
+
+        let trials = tester.results.total.trial_count as usize;
+        drop(tester);
+
+        let output = std::fs::read_to_string(csv.path()).unwrap();
+        let mut lines = output.lines();
+        assert_eq!(lines.next(), Some("time_elapsed,bytes_processed,pagefaults,gb_per_sec"));
+
+        let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), trials);
+
+        for row in rows {
+            assert_eq!(row.len(), 4);
+            assert!(row[0].parse::<i64>().unwrap() >= 0);
+            assert_eq!(row[1].parse::<u64>().unwrap(), BYTES);
+            row[2].parse::<i64>().unwrap();
+            row[3].parse::<f64>().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut tester = RepetitionTester::new(Duration::from_millis(50), 0);
+        assert_eq!(tester.percentile(50.0), None);
+
+        while tester.run_new_trial() {
+            tester.start_trial_timer();
+            let sum: u64 = (0..10_000u64).map(std::hint::black_box).sum();
+            tester.end_trial_timer();
+
+            assert_eq!(sum, 10_000 * 9_999 / 2);
+        }
+
+        let (min, max) = (tester.results().min.time_elapsed, tester.results().max.time_elapsed);
+        let median = tester.percentile(50.0).unwrap();
+
+        assert!(min <= median);
+        assert!(median <= max);
+        assert_eq!(tester.percentile(0.0), Some(min));
+        assert_eq!(tester.percentile(100.0), Some(max));
+        assert!(tester.percentile(99.0).unwrap() >= median);
+    }
+
+    #[test]
+    fn test_summary_of_synthetic_trials() {
+        let mut tester = RepetitionTester::new(Duration::from_secs(60), 1000);
+        assert_eq!(tester.summary(), None);
+
+        for time in [30, 10, 50, 20, 40] {
+            assert!(tester.run_new_trial());
+            tester.curr.time_elapsed = time;
+            tester.curr.bytes_processed = 1000;
+            tester.curr.pagefaults = time / 10;
+        }
+        tester.run_new_trial();
+
+        assert_eq!(tester.percentile(50.0), Some(30));
+        assert_eq!(tester.percentile(20.0), Some(10));
+        assert_eq!(tester.percentile(90.0), Some(50));
+
+        let summary = tester.summary().unwrap();
+        assert_eq!(summary.trial_count, 5);
+        assert_eq!((summary.min_cycles, summary.max_cycles), (10, 50));
+        assert_eq!(summary.avg_cycles, 30.0);
+        assert_eq!(summary.avg_pagefaults, 3.0);
+        assert!(summary.best_bytes_per_sec > summary.avg_bytes_per_sec);
+    }
+
+    #[test]
+    fn test_warmup_trials_not_recorded() {
+        const BYTES: u64 = 64;
+
+        let mut tester = RepetitionTester::new(Duration::from_millis(50), BYTES)
+            .with_warmup(Duration::from_millis(50));
+
+        let mut trials_run = 0;
+        while tester.run_new_trial() {
+            trials_run += 1;
+
+            tester.start_trial_timer();
+            let data = vec![1u8; BYTES as usize];
+            tester.end_trial_timer();
+
+            tester.count_bytes(data.len() as u64);
+        }
+
+        let recorded = tester.results().total.trial_count;
+        println!("{} warmup trials, {recorded} recorded", tester.warmup_trials());
+
+        assert!(tester.warmup_trials() > 0);
+        assert!(recorded > 0);
+        assert_eq!(trials_run, tester.warmup_trials() + recorded);
+        assert_eq!(tester.summary().unwrap().trial_count, recorded as usize);
+    }
+
+    #[test]
+    #[should_panic(expected = "different number of bytes")]
+    fn test_warmup_checks_bytes() {
+        let mut tester = RepetitionTester::new(Duration::from_millis(50), 64)
+            .with_warmup(Duration::from_secs(60));
+
+        while tester.run_new_trial() {
+            tester.count_bytes(32);
+        }
+    }
+
+    #[test]
+    fn test_checks_last_completed_trial() {
+        const BYTES: u64 = 64;
+
+        let mut tester = RepetitionTester::new(Duration::from_millis(20), BYTES);
+        while tester.run_new_trial() {
+            tester.start_trial_timer();
+            let data = vec![1u8; BYTES as usize];
+            tester.end_trial_timer();
+
+            tester.count_bytes(data.len() as u64);
+        }
+        assert!(tester.results().total.trial_count > 0);
+
+        // The window closes before the first trial, so there's nothing to compare against
+        let mut tester = RepetitionTester::new(Duration::ZERO, BYTES);
+        assert!(!tester.run_new_trial());
+    }
+
+    #[test]
+    #[should_panic(expected = "different number of bytes")]
+    fn test_checks_bytes_at_window_end() {
+        let mut tester = RepetitionTester::new(Duration::from_secs(60), 64);
+
+        assert!(tester.run_new_trial());
+        tester.count_bytes(32);
+
+        tester.end_time = 0;
+        tester.run_new_trial();
+    }
+
+    #[test]
+    fn test_reset_between_runs() {
+        let mut tester = RepetitionTester::new(Duration::from_secs(60), 0);
+
+        for time in [10, 20, 30] {
+            assert!(tester.run_new_trial());
+            tester.curr.time_elapsed = time;
+        }
+        tester.run_new_trial();
+        assert_eq!(tester.results().min.time_elapsed, 10);
+
+        tester.reset(Duration::from_secs(60), 100);
+        assert_eq!(tester.summary(), None);
+
+        for time in [50, 70] {
+            assert!(tester.run_new_trial());
+            tester.curr.time_elapsed = time;
+            tester.curr.bytes_processed = 100;
+        }
+        tester.run_new_trial();
+
+        let summary = tester.summary().unwrap();
+        assert_eq!(summary.trial_count, 2);
+        assert_eq!((summary.min_cycles, summary.max_cycles), (50, 70));
+        assert_eq!(summary.avg_cycles, 60.0);
+        assert_eq!(tester.percentile(50.0), Some(50));
+        assert!(!tester.last_was_new_min());
+    }
+
+    #[test]
+    fn test_last_was_new_min() {
+        let mut tester = RepetitionTester::new(Duration::from_secs(60), 0);
+        assert!(tester.run_new_trial());
+        assert!(!tester.last_was_new_min());
+
+        for time in [100, 80, 60, 40, 20] {
+            tester.curr.time_elapsed = time;
+            assert!(tester.run_new_trial());
+            assert!(tester.last_was_new_min(), "{time} should be a new min");
+        }
+
+        for time in [20, 30] {
+            tester.curr.time_elapsed = time;
+            assert!(tester.run_new_trial());
+            assert!(!tester.last_was_new_min(), "{time} shouldn't be a new min");
+        }
+    }
+
+    #[test]
+    fn test_new_min_callback() {
+        let mins = Rc::new(RefCell::new(Vec::new()));
+
+        let recorded = mins.clone();
+        let mut tester = RepetitionTester::new(Duration::from_secs(60), 0)
+            .with_new_min_callback(move |metrics| recorded.borrow_mut().push(metrics.time_elapsed));
+
+        for time in [50, 40, 45, 30, 30, 10, 20] {
+            assert!(tester.run_new_trial());
+            tester.curr.time_elapsed = time;
+        }
+        tester.run_new_trial();
+
+        assert_eq!(*mins.borrow(), [50, 40, 30, 10]);
+        assert_eq!(tester.results.min.time_elapsed, 10);
+    }
+
+    #[test]
+    fn repeat_parse_instrumentation() {
+        let path = &get_file();
+        let data = read_to_string_fast(&mut std::fs::File::open(path).unwrap());
+
+        for enabled in [true, false] {
+            println!("\nParse with profiler enabled: {enabled}");
+            set_profiler_enabled(enabled);
+
+            let mut tester = RepetitionTester::new(TEST_DUR, data.len() as u64);
+            while tester.run_new_trial() {
+                tester.start_trial_timer();
+                let json = JsonValue::parse(&data);
+                tester.end_trial_timer();
+
+                tester.count_bytes(data.len() as u64);
+                drop(json);
+            }
+        }
+
+        set_profiler_enabled(true);
+    }
+
+    #[test]
+    fn repeat_parse_pairs() {
+        let path = &get_file();
+        let data = read_to_string_fast(&mut std::fs::File::open(path).unwrap());
+
+        println!("\nParse tree, then read pairs:");
+        let mut tester = RepetitionTester::new(TEST_DUR, data.len() as u64);
+        while tester.run_new_trial() {
+            tester.start_trial_timer();
+            let json = JsonValue::parse(&data);
+            let sum: f64 = json["pairs"].elements().iter().map(|pair| f64::from(&pair["x0"])).sum();
+            tester.end_trial_timer();
+
+            tester.count_bytes(data.len() as u64);
+            assert!(sum.is_finite());
+            drop(json);
+        }
+
+        println!("\nStream pairs:");
+        let mut tester = RepetitionTester::new(TEST_DUR, data.len() as u64);
+        while tester.run_new_trial() {
+            tester.start_trial_timer();
+            let sum: f64 = parse_pairs(&data).map(|[x0, ..]| x0).sum();
+            tester.end_trial_timer();
+
+            tester.count_bytes(data.len() as u64);
+            assert!(sum.is_finite());
+        }
+    }
+
+    #[test]
+    fn repeat_haversine_approx() {
+        const PAIRS: usize = 1_000_000;
+
+        let mut rng = GenRng::seed_from_u64(1);
+        let pairs: Vec<[f64; 4]> = (0..PAIRS)
+            .map(|_| {
+                let mut coord = |bound: f64| rng.random_range(-bound..bound);
+                [coord(180.0), coord(90.0), coord(180.0), coord(90.0)]
+            })
+            .collect();
+
+        let bytes = size_of_val(pairs.as_slice()) as u64;
+        type HaversineFn = fn(f64, f64, f64, f64) -> f64;
+        let candidates: [(&str, HaversineFn); 2] =
+            [("reference", haversine_with::<StdMath>), ("approx", haversine_approx)];
+
+        // The approximation is instrumented per call, which would swamp the comparison
+        set_profiler_enabled(false);
+        for (label, haversine) in candidates {
+            println!("\nHaversine ({label}):");
+
+            let mut sum = 0.0;
+            let mut tester = RepetitionTester::new(TEST_DUR, bytes);
+            while tester.run_new_trial() {
+                tester.start_trial_timer();
+                for &[x0, y0, x1, y1] in &pairs {
+                    sum += haversine(x0, y0, x1, y1);
+                }
+                tester.end_trial_timer();
+
+                tester.count_bytes(bytes);
+            }
+
+            assert!(sum.is_finite());
+        }
+
+        set_profiler_enabled(true);
+    }
+
     #[test]
     fn repeat_read_to_string() {
         run_test(|path, tester| {
@@ -0,0 +1,278 @@
+//! Two f64 lanes on whichever vector instructions the target has (SSE2 on x86_64, NEON on
+//! aarch64), falling back to a plain array elsewhere. Only what the batched haversine needs is
+//! here, along with lane-wise versions of `math::PolyMath` so no lane has to call into libm.
+
+use std::{
+    f64::consts::{FRAC_PI_2, PI},
+    ops::{Add, Mul, Sub},
+};
+
+use crate::math::{ASIN_COEFFS, COS_COEFFS, SIN_COEFFS};
+
+#[cfg(target_arch = "x86_64")]
+mod imp {
+    use std::arch::x86_64::*;
+
+    pub type Inner = __m128d;
+
+    pub fn splat(x: f64) -> Inner {
+        unsafe { _mm_set1_pd(x) }
+    }
+
+    pub fn load(xs: &[f64; 2]) -> Inner {
+        unsafe { _mm_loadu_pd(xs.as_ptr()) }
+    }
+
+    pub fn store(x: Inner) -> [f64; 2] {
+        let mut out = [0.0; 2];
+        unsafe { _mm_storeu_pd(out.as_mut_ptr(), x) };
+        out
+    }
+
+    pub fn add(a: Inner, b: Inner) -> Inner {
+        unsafe { _mm_add_pd(a, b) }
+    }
+
+    pub fn sub(a: Inner, b: Inner) -> Inner {
+        unsafe { _mm_sub_pd(a, b) }
+    }
+
+    pub fn mul(a: Inner, b: Inner) -> Inner {
+        unsafe { _mm_mul_pd(a, b) }
+    }
+
+    pub fn sqrt(a: Inner) -> Inner {
+        unsafe { _mm_sqrt_pd(a) }
+    }
+
+    pub fn select_gt(a: Inner, b: Inner, if_gt: Inner, otherwise: Inner) -> Inner {
+        unsafe {
+            let mask = _mm_cmpgt_pd(a, b);
+            _mm_or_pd(_mm_and_pd(mask, if_gt), _mm_andnot_pd(mask, otherwise))
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod imp {
+    use std::arch::aarch64::*;
+
+    pub type Inner = float64x2_t;
+
+    pub fn splat(x: f64) -> Inner {
+        unsafe { vdupq_n_f64(x) }
+    }
+
+    pub fn load(xs: &[f64; 2]) -> Inner {
+        unsafe { vld1q_f64(xs.as_ptr()) }
+    }
+
+    pub fn store(x: Inner) -> [f64; 2] {
+        let mut out = [0.0; 2];
+        unsafe { vst1q_f64(out.as_mut_ptr(), x) };
+        out
+    }
+
+    pub fn add(a: Inner, b: Inner) -> Inner {
+        unsafe { vaddq_f64(a, b) }
+    }
+
+    pub fn sub(a: Inner, b: Inner) -> Inner {
+        unsafe { vsubq_f64(a, b) }
+    }
+
+    pub fn mul(a: Inner, b: Inner) -> Inner {
+        unsafe { vmulq_f64(a, b) }
+    }
+
+    pub fn sqrt(a: Inner) -> Inner {
+        unsafe { vsqrtq_f64(a) }
+    }
+
+    pub fn select_gt(a: Inner, b: Inner, if_gt: Inner, otherwise: Inner) -> Inner {
+        unsafe { vbslq_f64(vcgtq_f64(a, b), if_gt, otherwise) }
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod imp {
+    pub type Inner = [f64; 2];
+
+    pub fn splat(x: f64) -> Inner {
+        [x; 2]
+    }
+
+    pub fn load(xs: &[f64; 2]) -> Inner {
+        *xs
+    }
+
+    pub fn store(x: Inner) -> [f64; 2] {
+        x
+    }
+
+    pub fn add(a: Inner, b: Inner) -> Inner {
+        [a[0] + b[0], a[1] + b[1]]
+    }
+
+    pub fn sub(a: Inner, b: Inner) -> Inner {
+        [a[0] - b[0], a[1] - b[1]]
+    }
+
+    pub fn mul(a: Inner, b: Inner) -> Inner {
+        [a[0] * b[0], a[1] * b[1]]
+    }
+
+    pub fn sqrt(a: Inner) -> Inner {
+        [a[0].sqrt(), a[1].sqrt()]
+    }
+
+    pub fn select_gt(a: Inner, b: Inner, if_gt: Inner, otherwise: Inner) -> Inner {
+        [0, 1].map(|i| if a[i] > b[i] { if_gt[i] } else { otherwise[i] })
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct F64x2(imp::Inner);
+
+impl F64x2 {
+    pub const LANES: usize = 2;
+
+    pub fn splat(x: f64) -> Self {
+        Self(imp::splat(x))
+    }
+
+    /// Loads the first two elements of `xs`, panicking if there are fewer
+    pub fn from_slice(xs: &[f64]) -> Self {
+        Self(imp::load(xs[..Self::LANES].try_into().unwrap()))
+    }
+
+    pub fn to_array(self) -> [f64; 2] {
+        imp::store(self.0)
+    }
+
+    pub fn sum(self) -> f64 {
+        let [a, b] = self.to_array();
+        a + b
+    }
+
+    pub fn sqrt(self) -> Self {
+        Self(imp::sqrt(self.0))
+    }
+
+    /// Per lane, `if_gt` where `self > other` and `otherwise` everywhere else
+    pub fn select_gt(self, other: Self, if_gt: Self, otherwise: Self) -> Self {
+        Self(imp::select_gt(self.0, other.0, if_gt.0, otherwise.0))
+    }
+
+    /// Lane-wise version of `math::poly`
+    fn poly(self, coeffs: &[f64]) -> Self {
+        coeffs
+            .iter()
+            .rev()
+            .fold(Self::splat(0.0), |acc, c| acc * self + Self::splat(*c))
+    }
+
+    /// Within 1e-15 of `f64::sin` on [-pi, pi]
+    pub fn sin(self) -> Self {
+        let half_pi = Self::splat(FRAC_PI_2);
+
+        // The same folding as `PolyMath::sin`
+        let x = self.select_gt(
+            half_pi,
+            Self::splat(PI) - self,
+            (-half_pi).select_gt(self, Self::splat(-PI) - self, self),
+        );
+
+        x * (x * x).poly(&SIN_COEFFS)
+    }
+
+    /// Within 1e-15 of `f64::cos` on [-pi/2, pi/2], which covers every latitude
+    pub fn cos(self) -> Self {
+        (self * self).poly(&COS_COEFFS)
+    }
+
+    /// Within 1e-15 of `f64::asin` on [0, 1]
+    pub fn asin(self) -> Self {
+        let half = Self::splat(0.5);
+
+        // The same reduction as `PolyMath::asin`, chosen per lane
+        let reduced = ((Self::splat(1.0) - self) * half).sqrt();
+        let z = self.select_gt(half, reduced, self);
+        let asin_z = z * (z * z).poly(&ASIN_COEFFS);
+
+        self.select_gt(half, Self::splat(FRAC_PI_2) - asin_z - asin_z, asin_z)
+    }
+}
+
+impl Add for F64x2 {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(imp::add(self.0, rhs.0))
+    }
+}
+
+impl Sub for F64x2 {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(imp::sub(self.0, rhs.0))
+    }
+}
+
+impl Mul for F64x2 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self(imp::mul(self.0, rhs.0))
+    }
+}
+
+impl std::ops::Neg for F64x2 {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::splat(0.0) - self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::F64x2;
+
+    /// Max absolute error of `approx` against `exact` over evenly spaced points in [lo, hi]
+    fn max_error(lo: f64, hi: f64, approx: impl Fn(F64x2) -> F64x2, exact: impl Fn(f64) -> f64) -> f64 {
+        let steps = 100_000;
+        let xs: Vec<f64> = (0..=steps).map(|i| lo + (hi - lo) * i as f64 / steps as f64).collect();
+
+        xs.chunks_exact(F64x2::LANES)
+            .flat_map(|chunk| {
+                let approxed = approx(F64x2::from_slice(chunk)).to_array();
+                chunk.iter().zip(approxed).map(|(x, a)| (exact(*x) - a).abs()).collect::<Vec<_>>()
+            })
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn test_approximations() {
+        use std::f64::consts::{FRAC_PI_2, PI};
+
+        assert!(max_error(-PI, PI, F64x2::sin, f64::sin) < 1e-15);
+        assert!(max_error(-FRAC_PI_2, FRAC_PI_2, F64x2::cos, f64::cos) < 1e-15);
+        assert!(max_error(0.0, 1.0, F64x2::asin, f64::asin) < 1e-15);
+    }
+
+    #[test]
+    fn test_lanes() {
+        let a = F64x2::from_slice(&[1.0, 4.0, 9.0]);
+        let b = F64x2::splat(2.0);
+
+        assert_eq!((a + b).to_array(), [3.0, 6.0]);
+        assert_eq!((a - b).to_array(), [-1.0, 2.0]);
+        assert_eq!((a * b).to_array(), [2.0, 8.0]);
+        assert_eq!((-a).to_array(), [-1.0, -4.0]);
+        assert_eq!(a.sqrt().to_array(), [1.0, 2.0]);
+        assert_eq!(a.sum(), 5.0);
+        assert_eq!(a.select_gt(b, a, b).to_array(), [2.0, 4.0]);
+    }
+}
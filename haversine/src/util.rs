@@ -1,9 +1,9 @@
-use crate::calc::average_haversine;
-use crate::generate::gen_input;
+use crate::generate::{gen_input, Distribution};
 use crate::parse::JsonValue;
 use profiler::{clear_profiler, profile_report};
 use profiler_macro::instr;
 use std::ops::Index;
+use std::sync::OnceLock;
 use std::usize;
 use std::{
     fs::File,
@@ -65,6 +65,20 @@ impl<'a> JsonValue<'a> {
 
         pairs
     }
+
+    /// Looks up a nested value by a path like `pairs.0.x0` or `pairs[0].x0`, returning `None`
+    /// rather than panicking when a key or index is missing or a value isn't a container
+    pub fn get(&self, path: &str) -> Option<&JsonValue<'a>> {
+        path.split(['.', '[', ']'])
+            .filter(|segment| !segment.is_empty())
+            .try_fold(self, |value, segment| match value {
+                JsonValue::Object { pairs } => {
+                    pairs.iter().find(|(k, _)| *k == segment).map(|(_, v)| v)
+                }
+                JsonValue::Array { elements } => elements.get(segment.parse::<usize>().ok()?),
+                _ => None,
+            })
+    }
 }
 
 impl<'a> From<JsonValue<'a>> for f64 {
@@ -127,16 +141,63 @@ impl<'a> From<&JsonValue<'a>> for bool {
     }
 }
 
-pub fn test_samples(uniform: bool, samples: u64) {
+/// Relative tolerance for validating the computed average against the reference, read once
+/// from `HAVERSINE_EPSILON`. Defaults to 0, requiring exact equality.
+pub fn haversine_epsilon() -> f64 {
+    static EPSILON: OnceLock<f64> = OnceLock::new();
+
+    *EPSILON.get_or_init(|| read_epsilon("HAVERSINE_EPSILON", 0.0))
+}
+
+fn read_epsilon(name: &str, default: f64) -> f64 {
+    parse_epsilon(name, std::env::var(name).ok().as_deref(), default)
+}
+
+fn parse_epsilon(name: &str, var: Option<&str>, default: f64) -> f64 {
+    var.map_or(default, |eps| {
+        eps.parse().unwrap_or_else(|_| panic!("{name} '{eps}' is not a valid f64"))
+    })
+}
+
+/// Mean absolute error, in km, that `haversine_approx` may stray from the reference, read once
+/// from `HAVERSINE_APPROX_EPSILON`. Defaults to `DEFAULT_APPROX_EPSILON`.
+pub fn haversine_approx_epsilon() -> f64 {
+    static EPSILON: OnceLock<f64> = OnceLock::new();
+
+    *EPSILON.get_or_init(|| read_epsilon("HAVERSINE_APPROX_EPSILON", DEFAULT_APPROX_EPSILON))
+}
+
+pub const DEFAULT_APPROX_EPSILON: f64 = 1e-9;
+
+pub fn haversine_close(expected: f64, actual: f64, epsilon: f64) -> bool {
+    expected == actual || (expected - actual).abs() <= epsilon * expected.abs()
+}
+
+pub fn assert_haversine_close(expected: f64, actual: f64) {
+    let epsilon = haversine_epsilon();
+    assert!(
+        haversine_close(expected, actual, epsilon),
+        "Haversine avg {actual} differs from reference {expected} by more than a relative {epsilon}"
+    );
+}
+
+pub fn test_samples(distribution: Distribution, samples: u64) {
     clear_profiler();
     let tmpfile = tempfile::NamedTempFile::new().unwrap();
     let path = tmpfile.path().to_str().unwrap();
 
-    println!("Generating input -- uniform: {uniform}");
-    let expected = gen_input(path, uniform, samples).expect("Failed to generate input");
+    println!("Generating input -- {distribution:?}");
+    let expected = gen_input(path, distribution, samples).expect("Failed to generate input");
 
     println!("Finished gen input");
-    let (input_size, actual) = average_haversine(path).expect("Failed to calculate haversine");
+
+    #[cfg(feature = "stream_parse")]
+    let average = crate::calc::average_haversine_streaming;
+
+    #[cfg(not(feature = "stream_parse"))]
+    let average = crate::calc::average_haversine;
+
+    let (input_size, actual) = average(path).expect("Failed to calculate haversine");
 
     instr!("Output", {
         println!("-------------------------");
@@ -155,7 +216,7 @@ pub fn test_samples(uniform: bool, samples: u64) {
 
     println!();
 
-    assert_eq!(expected, actual);
+    assert_haversine_close(expected, actual);
 }
 
 /// # Safety
@@ -190,3 +251,52 @@ pub fn read_to_string_fast(f: &mut File) -> String {
 
     unsafe { String::from_utf8_unchecked(data) }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strict_epsilon() {
+        let epsilon = parse_epsilon("HAVERSINE_EPSILON", None, 0.0);
+        assert_eq!(epsilon, 0.0);
+
+        assert!(haversine_close(10_000.0, 10_000.0, epsilon));
+        assert!(!haversine_close(10_000.0, 10_000.000001, epsilon));
+    }
+
+    #[test]
+    fn test_json_get() {
+        let json = JsonValue::parse(r#"{"pairs": [{"x0": 1.5, "y0": -2}, {"x0": 3}], "count": 2}"#);
+
+        assert_eq!(json.get("pairs.0.x0"), Some(&JsonValue::Number(1.5)));
+        assert_eq!(json.get("pairs[1].x0"), Some(&JsonValue::Number(3.0)));
+        assert_eq!(json.get("pairs[0]"), Some(&json["pairs"][0]));
+        assert_eq!(json.get(""), Some(&json));
+
+        assert_eq!(json.get("pairs.1.y0"), None);
+        assert_eq!(json.get("pairs.2"), None);
+        assert_eq!(json.get("pairs.x0"), None);
+        assert_eq!(json.get("missing.x0"), None);
+
+        assert_eq!(json.get("count.x0"), None);
+        assert_eq!(json.get("pairs.0.x0.0"), None);
+    }
+
+    #[test]
+    fn test_loosened_epsilon() {
+        let epsilon = parse_epsilon("HAVERSINE_EPSILON", Some("1e-9"), 0.0);
+        assert_eq!(epsilon, 1e-9);
+
+        assert!(haversine_close(10_000.0, 10_000.000001, epsilon));
+        assert!(haversine_close(10_000.0, 9_999.999999, epsilon));
+        assert!(!haversine_close(10_000.0, 10_000.1, epsilon));
+    }
+
+    #[test]
+    fn test_epsilon_default() {
+        let name = "HAVERSINE_APPROX_EPSILON";
+        assert_eq!(parse_epsilon(name, None, DEFAULT_APPROX_EPSILON), DEFAULT_APPROX_EPSILON);
+        assert_eq!(parse_epsilon(name, Some("0.5"), DEFAULT_APPROX_EPSILON), 0.5);
+    }
+}
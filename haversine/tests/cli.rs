@@ -0,0 +1,58 @@
+use std::process::Command;
+
+fn run(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_haversine"))
+        .args(args)
+        .output()
+        .expect("Failed to run haversine");
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn line_value<'a>(output: &'a str, label: &str) -> &'a str {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix(label))
+        .unwrap_or_else(|| panic!("No '{label}' line in:\n{output}"))
+        .trim()
+}
+
+#[test]
+fn generate_then_compute() {
+    let json = tempfile::NamedTempFile::new().unwrap();
+    let path = json.path().to_str().unwrap();
+
+    let generated = run(&[
+        "generate", "--cluster", "--samples", "1000", "--out", path, "--seed", "42",
+    ]);
+    assert_eq!(line_value(&generated, "Seed:"), "42");
+
+    let computed = run(&["compute", path]);
+    assert_eq!(line_value(&computed, "Pair count:"), "1000");
+    assert_eq!(
+        line_value(&computed, "Input size:"),
+        std::fs::metadata(path).unwrap().len().to_string()
+    );
+    assert_eq!(
+        line_value(&computed, "Haversine avg:"),
+        line_value(&generated, "Expected avg:")
+    );
+}
+
+#[test]
+fn bad_args() {
+    for args in [&[][..], &["generate", "--samples", "10"], &["frobnicate"]] {
+        let output = Command::new(env!("CARGO_BIN_EXE_haversine"))
+            .args(args)
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success(), "{args:?} should fail");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("usage:"));
+    }
+}
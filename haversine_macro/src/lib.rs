@@ -23,14 +23,85 @@ pub fn repeat_asm(input: TokenStream) -> TokenStream {
     let instr_str = instruction.value();
     let count_val = count.base10_parse::<usize>().unwrap();
 
-    let repeated = (0..count_val)
-        .map(|_| format!("{}\n", instr_str))
-        .collect::<String>();
-
-    // Remove trailing newline
-    let repeated = repeated.trim_end();
+    let repeated = repeat(&instr_str, count_val);
 
     quote! {
         #repeated
     }.into()
 }
+
+/// Repeats `instr` on separate lines, replacing `{i}` with the repetition index and `{i*N}` with
+/// the index times N. Other `{...}` placeholders are left for `asm!`.
+fn repeat(instr: &str, count: usize) -> String {
+    let repeated = (0..count)
+        .map(|i| format!("{}\n", substitute_index(instr, i)))
+        .collect::<String>();
+
+    // Remove trailing newline
+    repeated.trim_end().to_string()
+}
+
+fn substitute_index(instr: &str, i: usize) -> String {
+    let mut res = String::new();
+    let mut rest = instr;
+
+    while let Some(start) = rest.find('{') {
+        res += &rest[..start];
+        rest = &rest[start..];
+
+        // `{{` is asm!'s escaped brace, so it and whatever follows are literal text
+        if let Some(after) = rest.strip_prefix("{{") {
+            res += "{{";
+            rest = after;
+            continue;
+        }
+
+        let end = rest.find('}').map_or(rest.len(), |end| end + 1);
+        let placeholder = &rest[..end];
+
+        let inner = placeholder.strip_prefix('{').unwrap_or(placeholder);
+        let value = match inner.strip_suffix('}').unwrap_or(inner) {
+            "i" => Some(i),
+            inner => inner
+                .strip_prefix("i*")
+                .and_then(|scale| scale.trim().parse::<usize>().ok())
+                .map(|scale| i * scale),
+        };
+
+        match value {
+            Some(value) => res += &value.to_string(),
+            None => res += placeholder,
+        }
+
+        rest = &rest[end..];
+    }
+
+    res + rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::repeat;
+
+    #[test]
+    fn test_repeat_plain() {
+        assert_eq!(repeat("nop", 3), "nop\nnop\nnop");
+    }
+
+    #[test]
+    fn test_repeat_index() {
+        assert_eq!(
+            repeat("ldr x9, [{base}, #{i*8}]", 3),
+            "ldr x9, [{base}, #0]\nldr x9, [{base}, #8]\nldr x9, [{base}, #16]"
+        );
+
+        assert_eq!(repeat("add x{i}, x{i}, #1", 2), "add x0, x0, #1\nadd x1, x1, #1");
+    }
+
+    #[test]
+    fn test_repeat_escaped_braces() {
+        assert_eq!(repeat("mov {{i}}, {i}", 2), "mov {{i}}, 0\nmov {{i}}, 1");
+        assert_eq!(repeat("ld1 {{v0.2d}}, [{base}]", 1), "ld1 {{v0.2d}}, [{base}]");
+        assert_eq!(repeat("{{{i}}}", 1), "{{0}}");
+    }
+}
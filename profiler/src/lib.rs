@@ -1,10 +1,15 @@
-use std::{cell::RefCell, usize};
+use std::{
+    cell::RefCell,
+    cmp::Reverse,
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+    usize,
+};
 
 
-use metrics::{cpu_time, cpu_to_duration};
-
-#[cfg(feature = "profile")]
-use metrics::cpu_timer_freq;
+use metrics::{cpu_time, cpu_timer_freq, cpu_to_duration};
 
 pub mod metrics;
 
@@ -14,16 +19,137 @@ thread_local! {
     pub static PROFILER: RefCell<Profiler> = const { RefCell::new(Profiler::new()) };
 }
 
+// Snapshots of registered threads' profilers, pushed as each thread exits
+static COLLECTED: Mutex<Vec<ProfileSnapshot>> = Mutex::new(Vec::new());
+
+/// Submits this thread's profile to the merged report when the thread exits
+pub fn register_thread() {
+    PROFILER.with(|p| p.borrow_mut().registered = true);
+}
+
+/// Profiles of every registered thread that has exited so far
+pub fn collect_all_threads() -> Vec<ProfileSnapshot> {
+    COLLECTED.lock().unwrap().clone()
+}
+
+/// This thread's profile merged with every collected thread's profile
+pub fn profile_snapshot_merged() -> ProfileSnapshot {
+    ProfileSnapshot::merge(collect_all_threads().into_iter().chain([profile_snapshot()]))
+}
+
+pub fn profile_report_merged() {
+    #[cfg(feature = "profile")]
+    profile_snapshot_merged().report();
+}
+
 pub fn profile_report() {
     #[cfg(feature = "profile")]
     PROFILER.with(|p| p.borrow().report());
 }
 
+/// `profile_report`, with the nodes sorted by `ProfileSnapshot::sort_by_exclusive`
+pub fn profile_report_by_exclusive() {
+    #[cfg(feature = "profile")]
+    {
+        let mut snapshot = profile_snapshot();
+        snapshot.sort_by_exclusive();
+        snapshot.report();
+    }
+}
+
+/// Adds to the innermost open block without timing anything, which is expected to be the one
+/// called `name`. This is what `instr_bytes!` expands to.
+pub fn add_bytes(name: &'static str, bytes_processed: usize) {
+    PROFILER.with(|p| {
+        let mut p = p.borrow_mut();
+        let id = p.current_node();
+        debug_assert!(
+            id == 0 || p.timers[id].as_ref().is_some_and(|node| node.name == name),
+            "Adding bytes for {name}, which isn't the innermost open block"
+        );
+
+        p.add_bytes(id, bytes_processed);
+    })
+}
+
+/// Owned copies of every node recorded on this thread, in the order `profile_report` prints them
+pub fn profile_snapshot() -> ProfileSnapshot {
+    PROFILER.with(|p| p.borrow().snapshot())
+}
+
+/// Emits every recorded span in the Chrome Tracing `traceEvents` format, which can be loaded
+/// into chrome://tracing or Perfetto. Spans are only recorded with the `trace` feature.
+pub fn report_chrome_trace() -> String {
+    #[cfg(feature = "trace")]
+    return PROFILER.with(|p| p.borrow().chrome_trace());
+
+    #[cfg(not(feature = "trace"))]
+    String::from("{\"traceEvents\": []}")
+}
+
+/// Turns recording on or off for this thread. Disabled blocks still cost a thread-local lookup,
+/// but record nothing, which helps measure how much instrumentation distorts a hot path.
+pub fn set_profiler_enabled(enabled: bool) {
+    PROFILER.with(|p| p.borrow_mut().disabled = !enabled);
+}
+
 pub fn clear_profiler() {
     #[cfg(feature = "profile")]
     PROFILER.set(Profiler::new());
 }
 
+/// Runs `f` once, returning its result and how many CPU timer ticks it took
+pub fn time<T>(f: impl FnOnce() -> T) -> (T, u64) {
+    let start = cpu_time();
+    let res = f();
+    (res, cpu_time() - start)
+}
+
+/// A named timer that's independent of the thread-local `PROFILER`. Repeated start/stop pairs
+/// accumulate into the same elapsed total.
+pub struct Timer {
+    name: &'static str,
+    /// Set while running
+    start: Option<u64>,
+    elapsed: u64,
+}
+
+impl Timer {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            start: None,
+            elapsed: 0,
+        }
+    }
+
+    pub fn start(&mut self) {
+        self.start = Some(cpu_time());
+    }
+
+    /// Does nothing if the timer isn't running
+    pub fn stop(&mut self) {
+        if let Some(start) = self.start.take() {
+            self.elapsed += cpu_time() - start;
+        }
+    }
+
+    /// Total over every start/stop pair so far, including the current one if still running
+    pub fn elapsed_cycles(&self) -> u64 {
+        self.elapsed + self.start.map_or(0, |start| cpu_time() - start)
+    }
+
+    pub fn report_standalone(&self) {
+        let elapsed = self.elapsed_cycles();
+        println!(
+            "{}: {:09.4}ms {} cycles",
+            self.name,
+            cpu_to_duration(elapsed).as_secs_f64() * 1_000.0,
+            elapsed
+        );
+    }
+}
+
 fn num_digits(num: u64) -> usize {
     (num.checked_ilog10().unwrap_or(0) + 1) as usize
 }
@@ -48,16 +174,45 @@ impl ProfileNode {
         }
     }
 
+    pub fn calls(&self) -> u64 {
+        self.calls
+    }
+
+    pub fn snapshot(&self, id: usize) -> ProfileNodeSnapshot {
+        ProfileNodeSnapshot {
+            id,
+            name: self.name,
+            calls: self.calls,
+            elapsed_inclusive: self.elapsed_inclusive,
+            elapsed_exclusive: self.elapsed_exclusive,
+            bytes_processed: self.bytes_processed,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProfileNodeSnapshot {
+    pub id: usize,
+    pub name: &'static str,
+    pub calls: u64,
+    pub elapsed_inclusive: u64,
+    pub elapsed_exclusive: i64,
+    pub bytes_processed: usize,
+}
+
+impl ProfileNodeSnapshot {
     pub fn report(&self, total_elapsed: u64) {
-        let p_exclusive = if self.elapsed_exclusive as u64 != self.elapsed_inclusive {
-            format!(
-                ", {} cycles ({:05.2}%) excluding children",
-                self.elapsed_exclusive,
-                (100 * self.elapsed_exclusive) as f64 / total_elapsed as f64
-            )
-        } else {
-            "".to_string()
-        };
+        println!("{}", self.report_line(total_elapsed));
+    }
+
+    /// Leaves without children still show their exclusive time, so every line can be compared
+    /// when looking for hotspots
+    pub fn report_line(&self, total_elapsed: u64) -> String {
+        let p_exclusive = format!(
+            ", {} cycles ({:05.2}%) excluding children",
+            self.elapsed_exclusive,
+            (100 * self.elapsed_exclusive) as f64 / total_elapsed as f64
+        );
 
         let p_vals = format!(
             "{:09.4}ms {:padding$} cycles ({:05.2}%){p_exclusive}",
@@ -80,17 +235,81 @@ impl ProfileNode {
             "".to_string()
         };
 
-        let padding = 35 - self.name.len() - num_digits(self.calls);
-        println!(
+        let padding = 35usize.saturating_sub(self.name.len() + num_digits(self.calls));
+        format!(
             "{}[{}]: {:padding$}{p_vals}{p_data}",
             self.name,
             self.calls,
             "",
             padding = padding,
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProfileSnapshot {
+    pub total_elapsed: u64,
+    pub nodes: Vec<ProfileNodeSnapshot>,
+}
+
+impl ProfileSnapshot {
+    /// Sums nodes with matching ids. The total is the sum of every thread's total, so
+    /// percentages are of all profiled thread time.
+    pub fn merge(snapshots: impl IntoIterator<Item = ProfileSnapshot>) -> ProfileSnapshot {
+        let mut merged = ProfileSnapshot {
+            total_elapsed: 0,
+            nodes: Vec::new(),
+        };
+
+        for snapshot in snapshots {
+            merged.total_elapsed += snapshot.total_elapsed;
+
+            for node in snapshot.nodes {
+                match merged.nodes.iter_mut().find(|n| n.id == node.id) {
+                    Some(existing) => {
+                        existing.calls += node.calls;
+                        existing.elapsed_inclusive += node.elapsed_inclusive;
+                        existing.elapsed_exclusive += node.elapsed_exclusive;
+                        existing.bytes_processed += node.bytes_processed;
+                    }
+                    None => merged.nodes.push(node),
+                }
+            }
+        }
+
+        merged
+    }
+
+    /// Orders nodes by descending time excluding children, so the hottest blocks come first
+    pub fn sort_by_exclusive(&mut self) {
+        self.nodes.sort_by_key(|n| Reverse(n.elapsed_exclusive));
+    }
+
+    pub fn report(&self) {
+        let pre = "Total time";
+        let padding = 37 - pre.len();
+        println!(
+            "{pre}: {:padding$}{:09.4}ms {} cycles (CPU freq {})",
+            "",
+            cpu_to_duration(self.total_elapsed).as_secs_f64() * 1_000.0,
+            self.total_elapsed,
+            cpu_timer_freq()
         );
+
+        for node in &self.nodes {
+            node.report(self.total_elapsed);
+        }
     }
 }
 
+#[cfg(feature = "trace")]
+#[derive(Debug, Clone, Copy)]
+struct TraceSpan {
+    node_id: usize,
+    start: u64,
+    elapsed: u64,
+}
+
 pub struct ProfiledBlock {
     start: u64,
     root_elapsed: u64,
@@ -100,9 +319,28 @@ pub struct ProfiledBlock {
 
 impl ProfiledBlock {
     pub fn new(name: &'static str, id: usize, bytes_processed: usize) -> Self {
+        Self::enter(name, id, bytes_processed, true)
+    }
+
+    /// Continues timing a call that was already counted, e.g. a future being polled again
+    pub fn resume(name: &'static str, id: usize) -> Self {
+        Self::enter(name, id, 0, false)
+    }
+
+    fn enter(name: &'static str, id: usize, bytes_processed: usize, new_call: bool) -> Self {
         PROFILER.with(|p| {
             let mut p = p.borrow_mut();
-            let parent_node_id = p.call_node(name, id, bytes_processed);
+            if p.disabled {
+                // Node 0 is never a real timer, so dropping this records nothing
+                return Self {
+                    start: 0,
+                    root_elapsed: 0,
+                    node_id: 0,
+                    parent_node_id: 0,
+                };
+            }
+
+            let parent_node_id = p.enter_node(name, id, bytes_processed, new_call);
             Self {
                 start: cpu_time(),
                 root_elapsed: p.timers[id].as_ref().unwrap().elapsed_inclusive,
@@ -115,6 +353,10 @@ impl ProfiledBlock {
 
 impl Drop for ProfiledBlock {
     fn drop(&mut self) {
+        if self.node_id == 0 {
+            return;
+        }
+
         PROFILER.with(|p| {
             let mut p = p.borrow_mut();
             let node = p.timers[self.node_id].as_mut().unwrap();
@@ -123,6 +365,13 @@ impl Drop for ProfiledBlock {
             node.elapsed_exclusive += elapsed as i64;
             node.elapsed_inclusive = self.root_elapsed + elapsed;
 
+            #[cfg(feature = "trace")]
+            p.spans.push(TraceSpan {
+                node_id: self.node_id,
+                start: self.start,
+                elapsed,
+            });
+
             if self.parent_node_id != 0 {
                 let parent = p.timers[self.parent_node_id].as_mut().unwrap();
                 parent.elapsed_exclusive -= elapsed as i64;
@@ -133,12 +382,64 @@ impl Drop for ProfiledBlock {
     }
 }
 
+/// Times a future only while it's being polled, so time spent suspended at an `.await` isn't
+/// counted. `#[instrument]` wraps the bodies of async fns in this.
+pub struct ProfiledFuture<F> {
+    inner: F,
+    name: &'static str,
+    id: usize,
+    bytes_processed: usize,
+    polled: bool,
+}
+
+impl<F: Future> ProfiledFuture<F> {
+    pub fn new(name: &'static str, id: usize, bytes_processed: usize, inner: F) -> Self {
+        Self {
+            inner,
+            name,
+            id,
+            bytes_processed,
+            polled: false,
+        }
+    }
+}
+
+impl<F: Future> Future for ProfiledFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: inner is never moved out of self, only re-pinned in place
+        let this = unsafe { self.get_unchecked_mut() };
+
+        let _handle = if this.polled {
+            ProfiledBlock::resume(this.name, this.id)
+        } else {
+            this.polled = true;
+            ProfiledBlock::new(this.name, this.id, this.bytes_processed)
+        };
+
+        unsafe { Pin::new_unchecked(&mut this.inner) }.poll(cx)
+    }
+}
+
 pub struct Profiler {
     timers: [Option<ProfileNode>; MAX_TIMERS],
     ordered: [usize; MAX_TIMERS],
     parent_node: usize,
     num_timers: usize,
     first_start: u64,
+    registered: bool,
+    disabled: bool,
+    #[cfg(feature = "trace")]
+    spans: Vec<TraceSpan>,
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        if self.registered && self.num_timers > 0 {
+            COLLECTED.lock().unwrap().push(self.snapshot());
+        }
+    }
 }
 
 impl Profiler {
@@ -149,10 +450,24 @@ impl Profiler {
             parent_node: 0,
             num_timers: 0,
             first_start: 0,
+            registered: false,
+            disabled: false,
+            #[cfg(feature = "trace")]
+            spans: Vec::new(),
         }
     }
 
     pub fn call_node(&mut self, name: &'static str, id: usize, bytes_processed: usize) -> usize {
+        self.enter_node(name, id, bytes_processed, true)
+    }
+
+    fn enter_node(
+        &mut self,
+        name: &'static str,
+        id: usize,
+        bytes_processed: usize,
+        new_call: bool,
+    ) -> usize {
         if self.timers[id].is_none() {
             if self.num_timers == 0 {
                 self.first_start = cpu_time();
@@ -165,7 +480,7 @@ impl Profiler {
         }
 
         let node = self.timers[id].as_mut().unwrap();
-        node.calls += 1;
+        node.calls += new_call as u64;
         node.bytes_processed += bytes_processed;
 
         let prev_par = self.parent_node;
@@ -173,22 +488,243 @@ impl Profiler {
         prev_par
     }
 
+    /// Unlike `call_node`, this neither counts a call nor opens a scope. Ids that don't have a node
+    /// yet, like 0 when no block is open, are ignored.
+    pub fn add_bytes(&mut self, id: usize, bytes_processed: usize) {
+        if let Some(node) = self.timers.get_mut(id).and_then(Option::as_mut) {
+            node.bytes_processed += bytes_processed;
+        }
+    }
+
+    /// The innermost block that's currently open, or 0 if there isn't one
+    pub fn current_node(&self) -> usize {
+        self.parent_node
+    }
+
+    pub fn node(&self, name: &str) -> Option<&ProfileNode> {
+        self.ordered[..self.num_timers]
+            .iter()
+            .filter_map(|id| self.timers[*id].as_ref())
+            .find(|node| node.name == name)
+    }
+
+    pub fn snapshot(&self) -> ProfileSnapshot {
+        ProfileSnapshot {
+            // Nothing has started the clock on a thread without timers
+            total_elapsed: if self.num_timers == 0 { 0 } else { cpu_time() - self.first_start },
+            nodes: self.ordered[..self.num_timers]
+                .iter()
+                .map(|id| self.timers[*id].as_ref().unwrap().snapshot(*id))
+                .collect(),
+        }
+    }
+
     #[cfg(feature = "profile")]
     fn report(&self) {
-        let total_elapsed = cpu_time() - self.first_start;
+        self.snapshot().report();
+    }
 
-        let pre = "Total time";
-        let padding = 37 - pre.len();
-        println!(
-            "{pre}: {:padding$}{:09.4}ms {} cycles (CPU freq {})",
-            "",
-            cpu_to_duration(total_elapsed).as_secs_f64() * 1_000.0,
-            total_elapsed,
-            cpu_timer_freq()
-        );
+    #[cfg(feature = "trace")]
+    fn chrome_trace(&self) -> String {
+        // Chrome expects timestamps and durations in microseconds
+        let to_micros = |cpu| cpu_to_duration(cpu).as_nanos() as f64 / 1_000.0;
+
+        let events = self
+            .spans
+            .iter()
+            .map(|span| {
+                let name = self.timers[span.node_id].as_ref().unwrap().name;
+                format!(
+                    "{{\"name\": \"{}\", \"ph\": \"X\", \"ts\": {:.3}, \"dur\": {:.3}, \"pid\": 0, \"tid\": 0}}",
+                    name.replace('\\', "\\\\").replace('"', "\\\""),
+                    to_micros(span.start - self.first_start),
+                    to_micros(span.elapsed),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",\n");
+
+        format!("{{\"traceEvents\": [\n{events}\n]}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hint::black_box;
 
-        for id in &self.ordered[..self.num_timers] {
-            self.timers[*id].as_ref().unwrap().report(total_elapsed);
+    use super::*;
+
+    #[test]
+    fn test_snapshot_nested() {
+        {
+            let _outer = ProfiledBlock::new("outer", 1, 0);
+            for _ in 0..3 {
+                let _inner = ProfiledBlock::new("inner", 2, 16);
+                black_box((0..10_000u64).sum::<u64>());
+            }
+            black_box((0..10_000u64).sum::<u64>());
+        }
+
+        let snapshot = profile_snapshot();
+        let [outer, inner] = &snapshot.nodes[..] else {
+            panic!("Expected exactly two nodes, got {:?}", snapshot.nodes);
+        };
+
+        assert_eq!((outer.name, outer.calls, outer.bytes_processed), ("outer", 1, 0));
+        assert_eq!((inner.name, inner.calls, inner.bytes_processed), ("inner", 3, 48));
+
+        assert!(outer.elapsed_exclusive < outer.elapsed_inclusive as i64);
+        assert!(outer.elapsed_inclusive >= inner.elapsed_inclusive);
+        assert_eq!(inner.elapsed_exclusive, inner.elapsed_inclusive as i64);
+        assert!(snapshot.total_elapsed >= outer.elapsed_inclusive);
+    }
+
+    #[test]
+    fn test_sort_by_exclusive() {
+        {
+            let _parent = ProfiledBlock::new("parent", 1, 0);
+            {
+                let _light = ProfiledBlock::new("light", 2, 0);
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            {
+                let _heavy = ProfiledBlock::new("heavy", 3, 0);
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            }
+        }
+
+        let mut snapshot = profile_snapshot();
+        let names = |s: &ProfileSnapshot| s.nodes.iter().map(|n| n.name).collect::<Vec<_>>();
+        assert_eq!(names(&snapshot), ["parent", "light", "heavy"]);
+
+        snapshot.sort_by_exclusive();
+        assert_eq!(names(&snapshot), ["heavy", "light", "parent"]);
+
+        for node in &snapshot.nodes {
+            let line = node.report_line(snapshot.total_elapsed);
+            assert!(line.contains("excluding children"), "{line}");
+        }
+    }
+
+    #[test]
+    fn test_time_closure() {
+        let (res, cycles) = time(|| {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            "done"
+        });
+
+        assert_eq!(res, "done");
+        assert!(cycles > 0);
+        assert!(cpu_to_duration(cycles) >= std::time::Duration::from_millis(4));
+    }
+
+    #[test]
+    fn test_standalone_timer() {
+        let mut timer = Timer::new("sleep");
+
+        timer.start();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        timer.stop();
+
+        timer.report_standalone();
+        assert!(timer.elapsed_cycles() > 0);
+        assert!(cpu_to_duration(timer.elapsed_cycles()) >= std::time::Duration::from_millis(4));
+        assert!(profile_snapshot().nodes.is_empty());
+
+        // Stopped, so nothing more accumulates
+        let stopped = timer.elapsed_cycles();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        timer.stop();
+        assert_eq!(timer.elapsed_cycles(), stopped);
+    }
+
+    #[test]
+    fn test_timer_elapsed_monotonic() {
+        let mut timer = Timer::new("monotonic");
+        assert_eq!(timer.elapsed_cycles(), 0);
+
+        let mut last = 0;
+        for _ in 0..3 {
+            timer.start();
+            for _ in 0..100 {
+                black_box((0..1_000u64).sum::<u64>());
+
+                let elapsed = timer.elapsed_cycles();
+                assert!(elapsed >= last, "{elapsed} < {last}");
+                last = elapsed;
+            }
+            timer.stop();
+
+            assert!(timer.elapsed_cycles() >= last);
+            last = timer.elapsed_cycles();
         }
+
+        assert!(last > 0);
+    }
+
+    #[test]
+    fn test_merged_threads() {
+        const ID: usize = 3;
+
+        let handles: Vec<_> = (1..=4)
+            .map(|calls| {
+                std::thread::spawn(move || {
+                    register_thread();
+                    for _ in 0..calls {
+                        let _block = ProfiledBlock::new("merged_work", ID, 8);
+                        black_box((0..1_000u64).sum::<u64>());
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let merged = profile_snapshot_merged();
+        let node = merged.nodes.iter().find(|n| n.name == "merged_work").unwrap();
+
+        assert_eq!(node.calls, 1 + 2 + 3 + 4);
+        assert_eq!(node.bytes_processed, 8 * 10);
+        assert!(merged.total_elapsed >= node.elapsed_inclusive);
+    }
+
+    #[test]
+    fn test_merged_without_timers() {
+        std::thread::spawn(|| {
+            register_thread();
+        })
+        .join()
+        .unwrap();
+
+        std::thread::spawn(|| {
+            assert_eq!(profile_snapshot().total_elapsed, 0);
+
+            // Other tests may still be adding threads, but never take any away
+            let merged = profile_snapshot_merged();
+            let collected: u64 = collect_all_threads().iter().map(|s| s.total_elapsed).sum();
+            assert!(merged.total_elapsed <= collected, "{} > {collected}", merged.total_elapsed);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_disabled() {
+        {
+            let _block = ProfiledBlock::new("enabled", 1, 0);
+        }
+
+        set_profiler_enabled(false);
+        for _ in 0..3 {
+            let _enabled = ProfiledBlock::new("enabled", 1, 0);
+            let _disabled = ProfiledBlock::new("disabled", 2, 0);
+        }
+        set_profiler_enabled(true);
+
+        let snapshot = profile_snapshot();
+        assert_eq!(snapshot.nodes.len(), 1);
+        assert_eq!(snapshot.nodes[0].calls, 1);
     }
 }
@@ -1,5 +1,12 @@
-use std::{arch::asm, mem::MaybeUninit, time::Duration};
+#[cfg(target_arch = "aarch64")]
+use std::arch::asm;
+use std::{
+    mem::MaybeUninit,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
 
+#[cfg(target_arch = "aarch64")]
 pub fn cpu_time() -> u64 {
     let mut x: u64;
     unsafe {
@@ -12,7 +19,37 @@ pub fn cpu_time() -> u64 {
     x
 }
 
+#[cfg(target_arch = "x86_64")]
+pub fn cpu_time() -> u64 {
+    unsafe { std::arch::x86_64::_rdtsc() }
+}
+
+/// Without a readable cycle counter, fall back to nanoseconds of wall-clock time since the
+/// first call
+#[cfg(not(any(target_arch = "aarch64", target_arch = "x86_64")))]
+pub fn cpu_time() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+
+    START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+static CPU_TIMER_FREQ: OnceLock<u64> = OnceLock::new();
+
+/// Cached after the first call, so conversions don't re-query (or re-estimate) the frequency
 pub fn cpu_timer_freq() -> u64 {
+    *CPU_TIMER_FREQ.get_or_init(read_cpu_timer_freq)
+}
+
+/// Overrides the frequency used by all conversions, e.g. with the result of
+/// `calibrate_cpu_freq`. Fails with the given frequency if it was already set or used.
+pub fn set_cpu_timer_freq(freq: u64) -> Result<(), u64> {
+    CPU_TIMER_FREQ.set(freq)
+}
+
+/// The counter rate reported by CNTFRQ_EL0. Some machines report a nominal rate here rather
+/// than the real one, so conversions use the calibrated frequency instead.
+#[cfg(target_arch = "aarch64")]
+pub fn register_cpu_timer_freq() -> u64 {
     let mut x: u64;
     unsafe {
         asm! (
@@ -24,19 +61,64 @@ pub fn cpu_timer_freq() -> u64 {
     x
 }
 
+#[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
+fn read_cpu_timer_freq() -> u64 {
+    calibrate_cpu_freq(Duration::from_millis(100))
+}
+
+#[cfg(not(any(target_arch = "aarch64", target_arch = "x86_64")))]
+fn read_cpu_timer_freq() -> u64 {
+    1_000_000_000
+}
+
+/// Measures how many timer ticks elapse over `wait` of OS time
+pub fn calibrate_cpu_freq(wait: Duration) -> u64 {
+    const SECS_TO_NANOS: u128 = 1_000_000_000;
+
+    let os_start = Instant::now();
+    let cpu_start = cpu_time();
+
+    let mut os_elapsed = os_start.elapsed();
+    while os_elapsed < wait {
+        os_elapsed = os_start.elapsed();
+    }
+
+    let cpu_elapsed = cpu_time() - cpu_start;
+
+    ((cpu_elapsed as u128 * SECS_TO_NANOS) / os_elapsed.as_nanos()) as u64
+}
+
+/// Page faults taken by this process so far, with minor faults (the page only needed mapping)
+/// and major faults (it had to be read from disk) summed together. Both counters are plain
+/// fault counts on Linux and macOS, only the `rusage` layout around them differs.
+#[cfg(unix)]
 pub fn pagefaults() -> u64 {
-    let mut usage = MaybeUninit::uninit();
+    let mut usage = MaybeUninit::<libc::rusage>::uninit();
     unsafe {
-        libc::getrusage(0, usage.as_mut_ptr());
+        if libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) != 0 {
+            return 0;
+        }
         let usage = usage.assume_init();
 
         usage.ru_minflt as u64 + usage.ru_majflt as u64
     }
 }
 
+/// There's no getrusage to ask, so no faults are ever reported
+#[cfg(not(unix))]
+pub fn pagefaults() -> u64 {
+    0
+}
+
+/// Whole seconds and the leftover nanoseconds are worked out separately, so any cycle count
+/// converts without overflowing, even at frequencies under 1GHz where the total nanoseconds
+/// wouldn't fit in a u64
 pub fn cpu_to_duration(cpu: u64) -> Duration {
     const SECS_TO_NANOS: u128 = 1_000_000_000;
-    Duration::from_nanos((cpu as u128 * SECS_TO_NANOS/cpu_timer_freq() as u128) as u64)
+
+    let freq = cpu_timer_freq();
+    let nanos = (cpu % freq) as u128 * SECS_TO_NANOS / freq as u128;
+    Duration::new(cpu / freq, nanos as u32)
 }
 
 pub fn duration_to_cpu(dur: Duration) -> u64 {
@@ -68,4 +150,121 @@ mod tests {
 
         assert_eq!(TEST_DUR_MILLIS, dur_millis as u64);
     }
+
+    #[test]
+    fn test_cpu_timer_freq_against_sleep() {
+        // Make sure the frequency is known before timing so estimation isn't measured
+        let freq = cpu_timer_freq();
+
+        let now = Instant::now();
+        let start = cpu_time();
+        std::thread::sleep(Duration::from_millis(100));
+        let end = cpu_time();
+        let os_elapsed = now.elapsed();
+
+        let measured = (end - start) as f64 / freq as f64;
+        let error = (measured - os_elapsed.as_secs_f64()).abs() / os_elapsed.as_secs_f64();
+
+        println!("CPU TIMER FREQ {freq}, measured {measured}s, os {os_elapsed:?}");
+        assert!(error < 0.03, "Timer frequency off by {:.2}%", error * 100.0);
+    }
+
+    #[test]
+    fn test_sleep_duration() {
+        const SLEEP: Duration = Duration::from_millis(100);
+
+        let start = cpu_time();
+        std::thread::sleep(SLEEP);
+        let measured = cpu_to_duration(cpu_time() - start);
+
+        println!("SLEPT {SLEEP:?}, MEASURED {measured:?}");
+        assert!(measured >= SLEEP.mul_f64(0.99));
+        assert!(measured < SLEEP.mul_f64(1.1));
+    }
+
+    #[test]
+    fn test_long_duration_conversion() {
+        // cpu * 1e9 overflows a u64 well before 10s worth of cycles at GHz frequencies
+        let cycles = cpu_timer_freq() * 10;
+
+        assert_eq!(cpu_to_duration(cycles), Duration::from_secs(10));
+        assert_eq!(duration_to_cpu(Duration::from_secs(10)), cycles);
+    }
+
+    #[test]
+    fn test_max_cycles_conversion() {
+        let freq = cpu_timer_freq();
+        let max = cpu_to_duration(u64::MAX);
+
+        assert_eq!(max.as_secs(), u64::MAX / freq);
+        assert!(max > cpu_to_duration(u64::MAX - freq));
+        assert_eq!(max - cpu_to_duration(u64::MAX - freq), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_calibrated_measurement() {
+        const TEST_DUR: Duration = Duration::from_millis(250);
+
+        // Make sure the frequency is known before timing so calibration isn't measured
+        cpu_timer_freq();
+
+        let now = Instant::now();
+        let start = cpu_time();
+        while now.elapsed() < TEST_DUR {}
+        let measured = cpu_to_duration(cpu_time() - start);
+        let actual = now.elapsed();
+
+        let error = (measured.as_secs_f64() - actual.as_secs_f64()).abs() / actual.as_secs_f64();
+
+        println!("MEASURED {measured:?}, ACTUAL {actual:?}");
+        assert!(error < 0.01, "Calibrated measurement off by {:.2}%", error * 100.0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pagefaults_touching_pages() {
+        const PAGES: usize = 256;
+        const PAGE_SIZE: usize = 4096;
+
+        let len = PAGES * PAGE_SIZE;
+        let mem = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(mem, libc::MAP_FAILED);
+
+        let start = pagefaults();
+        let mut prev = start;
+        for page in 0..PAGES {
+            unsafe { (mem as *mut u8).add(page * PAGE_SIZE).write_volatile(1) };
+
+            let curr = pagefaults();
+            assert!(curr >= prev, "Fault count went backwards ({prev} -> {curr})");
+            prev = curr;
+        }
+
+        println!("{} faults touching {PAGES} fresh pages", prev - start);
+        // The kernel may map a few pages ahead of a fault, so not every touch faults
+        assert!(prev - start >= (PAGES / 16) as u64);
+
+        unsafe { libc::munmap(mem, len) };
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn test_calibrate_against_register() {
+        let register = register_cpu_timer_freq();
+        let calibrated = calibrate_cpu_freq(Duration::from_millis(100));
+
+        let error = (calibrated as f64 - register as f64).abs() / register as f64;
+
+        println!("REGISTER FREQ {register}, CALIBRATED FREQ {calibrated}");
+        assert!(error < 0.01, "Calibrated frequency off by {:.2}%", error * 100.0);
+    }
 }
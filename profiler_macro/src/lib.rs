@@ -10,7 +10,7 @@ use quote::quote;
 
 use syn::{
     parse::{Parse, ParseStream},
-    Block, Expr, ExprLit, Ident, Lit, LitInt, LitStr,
+    Block, Expr, ExprLit, Ident, Lit, LitBool, LitInt, LitStr, ReturnType,
 };
 
 #[cfg(feature = "profile")]
@@ -22,20 +22,40 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 #[cfg(feature = "profile")]
 static COUNTER: AtomicUsize = AtomicUsize::new(1);
 
+// Must match MAX_TIMERS in the profiler crate, which indexes its timer table by id
+#[cfg(feature = "profile")]
+const MAX_TIMERS: usize = 4096;
+
 struct InstrumentArgs {
     name: Option<String>,
     bytes_processed: Option<Expr>,
+    skip_if: Option<Expr>,
     block: Option<Block>,
 }
 
 enum InstrumentArg {
     Name(String),
     BytesProcessed(Expr),
+    SkipIf(Expr),
     Block(Block),
 }
 
 impl Parse for InstrumentArg {
     fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(Ident) && input.peek2(syn::Token![=]) {
+            let key = input.parse::<Ident>()?;
+            input.parse::<syn::Token![=]>()?;
+
+            return match key.to_string().as_str() {
+                "skip_if" => Ok(Self::SkipIf(input.parse::<Expr>()?)),
+                "bytes" => Ok(Self::BytesProcessed(input.parse::<Expr>()?)),
+                _ => Err(syn::Error::new(
+                    key.span(),
+                    format!("Unknown instrument argument '{key}'"),
+                )),
+            };
+        }
+
         let lookahead = input.lookahead1();
 
         let res = if lookahead.peek(LitStr) {
@@ -59,11 +79,13 @@ impl Parse for InstrumentArgs {
 
         let mut name = None;
         let mut bytes_processed = None;
+        let mut skip_if = None;
         let mut block = None;
         for arg in args_parsed {
             match arg {
                 InstrumentArg::Name(n) => name = Some(n),
                 InstrumentArg::BytesProcessed(expr) => bytes_processed = Some(expr),
+                InstrumentArg::SkipIf(expr) => skip_if = Some(expr),
                 InstrumentArg::Block(b) => block = Some(b),
             }
         }
@@ -71,11 +93,31 @@ impl Parse for InstrumentArgs {
         Ok(InstrumentArgs {
             name,
             bytes_processed,
+            skip_if,
             block,
         })
     }
 }
 
+struct InstrBytesArgs {
+    name: LitStr,
+    bytes_processed: Expr,
+}
+
+impl Parse for InstrBytesArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse::<LitStr>()?;
+        input.parse::<syn::Token![,]>()?;
+        let bytes_processed = input.parse::<Expr>()?;
+        input.parse::<Option<syn::Token![,]>>()?;
+
+        Ok(Self {
+            name,
+            bytes_processed,
+        })
+    }
+}
+
 #[cfg(feature = "profile")]
 #[proc_macro_attribute]
 pub fn instrument(attr: TS, item: TS) -> TS {
@@ -83,23 +125,89 @@ pub fn instrument(attr: TS, item: TS) -> TS {
 
     let args: InstrumentArgs = parse_macro_input!(attr);
 
-    let vis = input_function.vis;
-    let name = input_function.sig.ident;
-    let arguments = input_function.sig.inputs;
-    let output = input_function.sig.output;
-    let block = input_function.block;
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = input_function;
+
+    if let Some(constness) = sig.constness {
+        return syn::Error::new(constness.span, "Can't instrument a const fn")
+            .to_compile_error()
+            .into();
+    }
 
+    let name = &sig.ident;
     let timer_name = args.name.unwrap_or(name.to_string());
-    let curr_index = get_and_increment_counter();
+    let curr_index = match next_timer_id(name.span()) {
+        Ok(id) => id,
+        Err(err) => return err.to_compile_error().into(),
+    };
 
-    quote! {
-        #vis fn #name(#arguments) #output {
-            {
-                let _handle = ::profiler::ProfiledBlock::new(#timer_name, #curr_index, 0);
+    // Evaluated inside the body, so it can refer to the function's parameters
+    let bytes_processed = args.bytes_processed.unwrap_or(Expr::Lit(ExprLit {
+        attrs: vec![],
+        lit: Lit::Int(LitInt::new("0", Span::call_site())),
+    }));
 
-                #block
+    if sig.asyncness.is_some() {
+        let output = match &sig.output {
+            ReturnType::Default => quote! { () },
+            ReturnType::Type(_, ty) => quote! { #ty },
+        };
+
+        let skip_if = args.skip_if.unwrap_or(Expr::Lit(ExprLit {
+            attrs: vec![],
+            lit: Lit::Bool(LitBool::new(false, Span::call_site())),
+        }));
+
+        // Only time spent polling the body is measured, not time suspended at an `.await`
+        return quote! {
+            #(#attrs)*
+            #[allow(clippy::let_and_return)]
+            #vis #sig {
+                let bytes_processed = #bytes_processed as usize;
+                let skip = #skip_if;
+
+                // Annotate the result so `?` and early returns infer the function's return type
+                let body = async move {
+                    let res: #output = #block;
+                    res
+                };
+
+                if skip {
+                    body.await
+                } else {
+                    ::profiler::ProfiledFuture::new(#timer_name, #curr_index, bytes_processed, body)
+                        .await
+                }
             }
         }
+        .into();
+    }
+
+    let handle = quote! {
+        ::profiler::ProfiledBlock::new(#timer_name, #curr_index, #bytes_processed as usize)
+    };
+
+    // Calls matching skip_if never construct a block, so they aren't counted at all
+    let handle = match args.skip_if {
+        Some(skip_if) => quote! { if #skip_if { None } else { Some(#handle) } },
+        None => handle,
+    };
+
+    quote! {
+        #(#attrs)*
+        #[allow(clippy::let_and_return)]
+        #vis #sig {
+            let _handle = #handle;
+
+            // Bind the result so the handle is dropped, and the time recorded, only after the
+            // body's value has been computed. Early returns and `?` drop it on the way out.
+            let res = #block;
+            res
+        }
     }
     .into()
 }
@@ -123,7 +231,10 @@ pub fn instr(item: TS) -> TS {
             lit: Lit::Int(LitInt::new("0", Span::call_site())),
         }));
 
-        let curr_index = get_and_increment_counter();
+        let curr_index = match next_timer_id(Span::call_site()) {
+            Ok(id) => id,
+            Err(err) => return err.to_compile_error().into(),
+        };
 
         quote! {
             {
@@ -139,7 +250,47 @@ pub fn instr(item: TS) -> TS {
     block.into_token_stream().into()
 }
 
+/// `instr_bytes!(name, bytes)` adds to the bytes of the enclosing block called `name`, without
+/// opening a timing scope of its own. Meant for hot loops, where a block per iteration would cost
+/// more than the work it's measuring.
+#[proc_macro]
+pub fn instr_bytes(item: TS) -> TS {
+    let input = match syn::parse2::<InstrBytesArgs>(item.into()) {
+        Ok(input) => input,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    #[cfg(feature = "profile")]
+    {
+        let InstrBytesArgs {
+            name,
+            bytes_processed,
+        } = input;
+
+        quote! {
+            ::profiler::add_bytes(#name, (#bytes_processed) as usize)
+        }
+        .into()
+    }
+
+    #[cfg(not(feature = "profile"))]
+    TS::new()
+}
+
+/// Ids are handed out in expansion order, starting from 1 since 0 marks a block with no parent.
+/// Running out is a compile error rather than an out-of-bounds panic at runtime.
 #[cfg(feature = "profile")]
-fn get_and_increment_counter() -> usize {
-    COUNTER.fetch_add(1, Ordering::SeqCst)
+fn next_timer_id(span: Span) -> syn::Result<usize> {
+    let id = COUNTER.fetch_add(1, Ordering::SeqCst);
+    if id >= MAX_TIMERS {
+        return Err(syn::Error::new(
+            span,
+            format!(
+                "Too many instrumented blocks, the profiler supports at most {}",
+                MAX_TIMERS - 1
+            ),
+        ));
+    }
+
+    Ok(id)
 }
@@ -0,0 +1,242 @@
+#![cfg(feature = "profile")]
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use profiler::{metrics::cpu_to_duration, profile_snapshot, PROFILER};
+use profiler_macro::{instr, instr_bytes, instrument};
+
+#[instrument(skip_if = n < 1000)]
+fn sum_to(n: u64) -> u64 {
+    (0..n).sum()
+}
+
+#[test]
+fn test_skip_if() {
+    for n in [10, 999, 1000, 500, 5000] {
+        assert_eq!(sum_to(n), n * (n - 1) / 2);
+    }
+
+    assert_eq!(calls("sum_to"), Some(2));
+}
+
+#[instrument]
+fn answer() -> u64 {
+    42
+}
+
+#[instrument]
+fn clamp_to_ten(n: u64) -> u64 {
+    if n > 10 {
+        return 10;
+    }
+
+    n
+}
+
+#[instrument]
+fn parse_sum(a: &str, b: &str) -> Result<u64, std::num::ParseIntError> {
+    Ok(a.parse::<u64>()? + b.parse::<u64>()?)
+}
+
+fn calls(name: &str) -> Option<u64> {
+    PROFILER.with(|p| p.borrow().node(name).map(|node| node.calls()))
+}
+
+#[test]
+fn test_return_value() {
+    assert_eq!(answer(), 42);
+    assert_eq!(calls("answer"), Some(1));
+}
+
+#[test]
+fn test_early_return() {
+    assert_eq!(clamp_to_ten(5), 5);
+    assert_eq!(clamp_to_ten(50), 10);
+    assert_eq!(calls("clamp_to_ten"), Some(2));
+}
+
+#[test]
+fn test_question_mark() {
+    assert_eq!(parse_sum("1", "2"), Ok(3));
+    assert!(parse_sum("1", "x").is_err());
+    assert_eq!(calls("parse_sum"), Some(2));
+}
+
+#[instrument(bytes = data.len())]
+fn count_spaces(data: &str) -> usize {
+    data.bytes().filter(|b| *b == b' ').count()
+}
+
+#[test]
+fn test_bytes_processed() {
+    assert_eq!(count_spaces("a b c"), 2);
+    assert_eq!(count_spaces("hello world!"), 1);
+
+    let snapshot = profile_snapshot();
+    let node = snapshot.nodes.iter().find(|n| n.name == "count_spaces").unwrap();
+    assert_eq!(node.calls, 2);
+    assert_eq!(node.bytes_processed, 5 + 12);
+}
+
+#[test]
+fn test_instr_bytes() {
+    let data = [0u8; 16];
+
+    instr!("byte_loop", {
+        for _ in 0..1000 {
+            instr_bytes!("byte_loop", data.len());
+        }
+    });
+
+    let snapshot = profile_snapshot();
+    let node = snapshot.nodes.iter().find(|n| n.name == "byte_loop").unwrap();
+    assert_eq!(node.calls, 1);
+    assert_eq!(node.bytes_processed, 16 * 1000);
+}
+
+struct Accumulator {
+    total: u64,
+}
+
+impl Accumulator {
+    #[instrument]
+    fn add(&mut self, n: u64) -> &mut Self {
+        self.total += n;
+        self
+    }
+
+    #[instrument]
+    fn sum_all<I>(&self, values: I) -> u64
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        self.total + values.into_iter().sum::<u64>()
+    }
+}
+
+#[instrument]
+unsafe fn read_ptr<T: Copy>(ptr: *const T) -> T {
+    *ptr
+}
+
+#[test]
+fn test_methods_and_generics() {
+    let mut acc = Accumulator { total: 0 };
+    acc.add(1).add(2);
+
+    assert_eq!(acc.total, 3);
+    assert_eq!(acc.sum_all([4, 5]), 12);
+    assert_eq!(acc.sum_all(vec![1]), 4);
+    assert_eq!(unsafe { read_ptr(&7u8) }, 7);
+
+    assert_eq!(calls("add"), Some(2));
+    assert_eq!(calls("sum_all"), Some(2));
+}
+
+macro_rules! instrumented_sites {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[instrument]
+            fn $name() -> &'static str {
+                stringify!($name)
+            }
+        )*
+
+        const SITES: &[fn() -> &'static str] = &[$($name),*];
+    };
+}
+
+instrumented_sites!(
+    site_00, site_01, site_02, site_03, site_04, site_05, site_06, site_07, site_08, site_09,
+    site_10, site_11, site_12, site_13, site_14, site_15, site_16, site_17, site_18, site_19,
+    site_20, site_21, site_22, site_23, site_24, site_25, site_26, site_27, site_28, site_29,
+    site_30, site_31, site_32, site_33, site_34, site_35, site_36, site_37, site_38, site_39,
+);
+
+#[test]
+fn test_unique_ids() {
+    let names = SITES.iter().map(|site| site()).collect::<Vec<_>>();
+
+    let snapshot = profile_snapshot();
+    let nodes = snapshot
+        .nodes
+        .iter()
+        .filter(|node| node.name.starts_with("site_"))
+        .collect::<Vec<_>>();
+
+    let mut ids = nodes.iter().map(|node| node.id).collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), SITES.len());
+
+    for name in names {
+        let node = nodes.iter().find(|node| node.name == name);
+        assert_eq!(node.map(|node| node.calls), Some(1), "{name}");
+    }
+}
+
+/// Returns `Pending` the first time it's polled, like an `.await` on I/O that isn't ready
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            Poll::Pending
+        }
+    }
+}
+
+#[instrument]
+async fn busy_task(rounds: u32) -> u32 {
+    for _ in 0..rounds {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(2) {}
+
+        YieldNow(false).await;
+    }
+
+    rounds
+}
+
+#[test]
+fn test_async_active_time() {
+    let mut tasks = [Box::pin(busy_task(5)), Box::pin(busy_task(5))];
+    let mut done = [None, None];
+
+    // Round-robin both tasks, idling between rounds as if waiting on I/O
+    let mut cx = Context::from_waker(Waker::noop());
+    let wall = Instant::now();
+    while done.iter().any(Option::is_none) {
+        for (task, done) in tasks.iter_mut().zip(&mut done) {
+            if done.is_none() {
+                if let Poll::Ready(res) = task.as_mut().poll(&mut cx) {
+                    *done = Some(res);
+                }
+            }
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let wall = wall.elapsed();
+
+    assert_eq!(done, [Some(5), Some(5)]);
+
+    let snapshot = profile_snapshot();
+    let node = snapshot.nodes.iter().find(|n| n.name == "busy_task").unwrap();
+    assert_eq!(node.calls, 2);
+
+    let active = cpu_to_duration(node.elapsed_inclusive);
+    println!("ACTIVE {active:?}, WALL {wall:?}");
+    assert!(active >= Duration::from_millis(20));
+    assert!(active < wall / 2);
+}
@@ -1,11 +1,15 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    io::{stdout, BufRead, Write},
+    str::FromStr,
+};
 
 use crate::{
-    assemble,
-    parse::{EffAddr, Inst, Operand, Register},
+    assemble_or_fixture, fixture_path,
+    parse::{EffAddr, Inst, Operand, Prefix, Register},
 };
 
-const REGISTER_SIZE: usize = 8 * 2;
+const REGISTER_SIZE: usize = 12 * 2;
 const MEM_SIZE: usize = 1 << 16;
 
 struct GeneralRegisters {
@@ -40,6 +44,11 @@ impl GeneralRegisters {
             Register::BP => (10, true),
             Register::SI => (12, true),
             Register::DI => (14, true),
+
+            Register::ES => (16, true),
+            Register::CS => (18, true),
+            Register::SS => (20, true),
+            Register::DS => (22, true),
         }
     }
 
@@ -56,8 +65,6 @@ impl GeneralRegisters {
     pub fn set_reg(&mut self, reg: Register, val: u16) {
         let (pos, wide) = Self::reg_pos(reg);
 
-        let before = self.get_reg(reg);
-
         if wide {
             let bytes = val.to_le_bytes();
             self.reg_array[pos] = bytes[0];
@@ -65,11 +72,43 @@ impl GeneralRegisters {
         } else {
             self.reg_array[pos] = val as u8;
         };
+    }
+}
+
+/// How register and instruction pointer values are shown in the execution trace
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Radix {
+    #[default]
+    Hex,
+    Decimal,
+}
+
+impl Radix {
+    pub fn format(self, val: usize) -> String {
+        match self {
+            Radix::Hex => format!("0x{val:x}"),
+            Radix::Decimal => format!("{val}"),
+        }
+    }
 
-        print!(" {reg}:0x{before:x}->0x{:x}", self.get_reg(reg))
+    /// Renders a single traced change, e.g. ` ax:0x1->0x2`
+    pub fn format_change(self, name: &str, before: usize, after: usize) -> String {
+        format!(" {name}:{}->{}", self.format(before), self.format(after))
     }
 }
 
+/// How much of each executed instruction is written to the trace. Each level includes the ones
+/// before it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TraceMode {
+    #[default]
+    Silent,
+    /// A line per instruction of the registers and flags it changed
+    Registers,
+    /// The instruction, its clocks and the ip change too, as in the course's reference output
+    Full,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum Flag {
     Parity = 1 << 2,
@@ -89,12 +128,51 @@ impl Display for Flag {
     }
 }
 
+/// Whether the Parity flag is set for a result. The 8086 only looks at the low byte, even for
+/// word results, and sets it when that byte has an even number of 1 bits.
+pub fn parity_of(low_byte: u8) -> bool {
+    low_byte.count_ones().is_multiple_of(2)
+}
+
+const WIDE_REGISTERS: [Register; 12] = [
+    Register::AX,
+    Register::CX,
+    Register::DX,
+    Register::BX,
+    Register::SP,
+    Register::BP,
+    Register::SI,
+    Register::DI,
+    Register::ES,
+    Register::CS,
+    Register::SS,
+    Register::DS,
+];
+
+/// What a single instruction changed, each as (before, after)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StateDelta {
+    /// Only registers whose value changed, by their full 16-bit name, segment registers included
+    pub regs: Vec<(Register, u16, u16)>,
+    pub flags: (u16, u16),
+    /// Only bytes whose value changed, by address
+    pub memory: Vec<(usize, u8, u8)>,
+    pub iptr: (usize, usize),
+}
+
 pub struct State {
     regs: GeneralRegisters,
     pub memory: [u8; MEM_SIZE],
     iptr: usize,
     flags: u16,
     cycles_estimate: u32,
+    memory_transfers: u32,
+    pub radix: Radix,
+    pub trace_mode: TraceMode,
+    // While set, the trace is collected here instead of going to stdout
+    captured_trace: Option<String>,
+    // Set on reaching an instruction that can't be simulated, after which nothing more runs
+    halted: bool,
 }
 
 impl State {
@@ -110,9 +188,42 @@ impl State {
             iptr: 0,
             flags: 0,
             cycles_estimate: 0,
+            memory_transfers: 0,
+            radix: Radix::default(),
+            trace_mode: TraceMode::default(),
+            captured_trace: None,
+            halted: false,
         }
     }
 
+    /// Collects the trace from here on, rather than printing it, until it's taken
+    pub fn capture_trace(&mut self) {
+        self.captured_trace.get_or_insert_with(String::new);
+    }
+
+    /// Everything traced since `capture_trace`, after which the trace goes back to stdout
+    pub fn take_trace(&mut self) -> String {
+        self.captured_trace.take().unwrap_or_default()
+    }
+
+    fn trace(&mut self, level: TraceMode, text: &str) {
+        if self.trace_mode < level {
+            return;
+        }
+
+        match self.captured_trace.as_mut() {
+            Some(captured) => captured.push_str(text),
+            None => print!("{text}"),
+        }
+    }
+
+    /// Up to `len` bytes of memory from `start`, cut short at the end of memory
+    pub fn dump_region(&self, start: usize, len: usize) -> &[u8] {
+        let start = start.min(MEM_SIZE);
+        &self.memory[start..MEM_SIZE.min(start + len)]
+    }
+
+    /// Memory is a flat 64KB, so segments (and overrides of them) don't factor in
     fn calc_addr(&self, eff_addr: EffAddr) -> usize {
         (eff_addr.base.map_or(0, |r| self.regs.get_reg(r) as i16)
             + eff_addr.index.map_or(0, |r| self.regs.get_reg(r) as i16)
@@ -129,13 +240,23 @@ impl State {
                 let addr = self.calc_addr(ea);
                 u16::from_le_bytes([self.memory[addr], self.memory[addr + 1]])
             }
-            Operand::RelOffsetByte(_) => todo!(),
+            Operand::RelOffsetByte(_) | Operand::RelOffsetWord(_) => todo!(),
         }
     }
 
     pub fn set_value(&mut self, op: Operand, val: u16) {
         match op {
-            Operand::Reg(reg) => self.regs.set_reg(reg, val),
+            Operand::Reg(reg) => {
+                let before = self.regs.get_reg(reg);
+                self.regs.set_reg(reg, val);
+
+                let change = self.radix.format_change(
+                    &reg.to_string(),
+                    before as usize,
+                    self.regs.get_reg(reg) as usize,
+                );
+                self.trace(TraceMode::Registers, &change);
+            }
             Operand::ImmByte(_) => panic!("Can't set an immediate value"),
             Operand::ImmWord(_) => panic!("Can't set an immediate value"),
             Operand::MemByte(ea) => self.memory[self.calc_addr(ea)] = val as u8,
@@ -145,7 +266,9 @@ impl State {
                 self.memory[addr] = bytes[0];
                 self.memory[addr + 1] = bytes[1];
             }
-            Operand::RelOffsetByte(_) => panic!("Can't set an immediate value"),
+            Operand::RelOffsetByte(_) | Operand::RelOffsetWord(_) => {
+                panic!("Can't set an immediate value")
+            }
         }
     }
 
@@ -179,28 +302,32 @@ impl State {
         s
     }
 
-    pub fn update_flags_from_value(&mut self, val: u16) {
+    /// Byte results only count their low byte, so the sign comes from bit 7 rather than bit 15
+    pub fn update_flags_from_value(&mut self, val: u16, wide: bool) {
         let before = self.flags_as_string();
 
+        let (val, sign_bit) = if wide { (val, 0x8000) } else { (val & 0xff, 0x80) };
+
         if val == 0 {
             self.set_flag(Flag::Zero);
         } else {
             self.unset_flag(Flag::Zero)
         }
 
-        if val > i16::MAX as u16 {
+        if val & sign_bit != 0 {
             self.set_flag(Flag::Signed)
         } else {
             self.unset_flag(Flag::Signed)
         }
 
-        if val.to_le_bytes()[0].count_ones() % 2 == 0 {
+        if parity_of(val.to_le_bytes()[0]) {
             self.set_flag(Flag::Parity)
         } else {
             self.unset_flag(Flag::Parity)
         }
 
-        print!(" flags:{before}->{}", self.flags_as_string())
+        let change = format!(" flags:{before}->{}", self.flags_as_string());
+        self.trace(TraceMode::Registers, &change);
     }
 
     pub fn jump(&mut self, op: Operand, condition: bool) {
@@ -215,6 +342,10 @@ impl State {
                     .iptr
                     .checked_add_signed(r as isize)
                     .expect("iptr addtion overflowed"),
+                Operand::RelOffsetWord(r) => self
+                    .iptr
+                    .checked_add_signed(r as isize)
+                    .expect("iptr addtion overflowed"),
             };
             self.iptr = jump_to;
         }
@@ -229,32 +360,261 @@ impl State {
         return Some(parsed);
     }
 
+    /// The stack grows down from sp, which always points at the last word pushed
+    fn push(&mut self, val: u16) {
+        let sp = self.regs.get_reg(Register::SP).wrapping_sub(2);
+        self.set_value(Operand::Reg(Register::SP), sp);
+
+        let sp = sp as usize;
+        self.memory[sp..sp + 2].copy_from_slice(&val.to_le_bytes());
+    }
+
+    fn pop(&mut self) -> u16 {
+        let sp = self.regs.get_reg(Register::SP);
+        let val = u16::from_le_bytes([self.memory[sp as usize], self.memory[sp as usize + 1]]);
+
+        self.set_value(Operand::Reg(Register::SP), sp.wrapping_add(2));
+        val
+    }
+
     fn dec(&mut self, op: Operand) {
         let dec = self.get_value(op).wrapping_sub(1);
 
         self.set_value(op, dec);
     }
 
+    /// Carries out an already-decoded instruction, whose bytes `iptr` has already moved past
+    fn execute(&mut self, inst: Inst) {
+        match inst {
+            Inst::MOV(op1, op2) => self.set_value(op1, self.get_value(op2)),
+            Inst::ADD(op1, op2) => {
+                let add = self.get_value(op1).wrapping_add(self.get_value(op2));
+                self.set_value(op1, add);
+                self.update_flags_from_value(add, op1.is_wide());
+            }
+            Inst::SUB(op1, op2) => {
+                let sub = self.get_value(op1).wrapping_sub(self.get_value(op2));
+                self.set_value(op1, sub);
+                self.update_flags_from_value(sub, op1.is_wide());
+            }
+            Inst::CMP(op1, op2) => {
+                let sub = self.get_value(op1).wrapping_sub(self.get_value(op2));
+                self.update_flags_from_value(sub, op1.is_wide());
+            }
+            Inst::TEST(op1, op2) => {
+                let and = self.get_value(op1) & self.get_value(op2);
+                self.update_flags_from_value(and, op1.is_wide());
+            }
+            // Carry and overflow would be cleared by the logical ops too, but they aren't tracked
+            Inst::OR(op1, op2) => {
+                let or = self.get_value(op1) | self.get_value(op2);
+                self.set_value(op1, or);
+                self.update_flags_from_value(or, op1.is_wide());
+            }
+            Inst::AND(op1, op2) => {
+                let and = self.get_value(op1) & self.get_value(op2);
+                self.set_value(op1, and);
+                self.update_flags_from_value(and, op1.is_wide());
+            }
+            Inst::XOR(op1, op2) => {
+                let xor = self.get_value(op1) ^ self.get_value(op2);
+                self.set_value(op1, xor);
+                self.update_flags_from_value(xor, op1.is_wide());
+            }
+            // Only the address is worked out, memory isn't touched
+            Inst::LEA(op1, Operand::MemWord(ea)) => {
+                self.set_value(op1, self.calc_addr(ea) as u16)
+            }
+            Inst::LEA(_, op2) => panic!("Can't load the address of {op2}"),
+            Inst::INC(op) => {
+                let inc = self.get_value(op).wrapping_add(1);
+                self.set_value(op, inc);
+                self.update_flags_from_value(inc, op.is_wide());
+            }
+            Inst::DEC(op) => {
+                let dec = self.get_value(op).wrapping_sub(1);
+                self.set_value(op, dec);
+                self.update_flags_from_value(dec, op.is_wide());
+            }
+            // The 8086 pushes sp as it is after the decrement
+            Inst::PUSH(Operand::Reg(Register::SP)) => {
+                self.push(self.regs.get_reg(Register::SP).wrapping_sub(2))
+            }
+            Inst::PUSH(op) => self.push(self.get_value(op)),
+            Inst::POP(op) => {
+                let val = self.pop();
+                self.set_value(op, val);
+            }
+            Inst::PUSHF => self.push(self.flags),
+            Inst::POPF => {
+                let before = self.flags_as_string();
+                self.flags = self.pop();
+
+                let change = format!(" flags:{before}->{}", self.flags_as_string());
+                self.trace(TraceMode::Registers, &change);
+            }
+            // Carry isn't tracked, so there's nothing for these to add in or borrow
+            Inst::ADC(..) | Inst::SBB(..) => self.halt_unsupported(&inst),
+            Inst::MOVS { .. }
+            | Inst::CMPS { .. }
+            | Inst::SCAS { .. }
+            | Inst::LODS { .. }
+            | Inst::STOS { .. } => self.halt_unsupported(&inst),
+            Inst::JO(_op) => todo!(),
+            Inst::JNO(_op) => todo!(),
+            Inst::JB(_op) => todo!(),
+            Inst::JNB(_op) => todo!(),
+            Inst::JE(_op) => todo!(),
+            Inst::JNE(op) => self.jump(op, !self.is_set(Flag::Zero)),
+            Inst::JBE(_op) => todo!(),
+            Inst::JNBE(_op) => todo!(),
+            Inst::JS(_op) => todo!(),
+            Inst::JNS(_op) => todo!(),
+            Inst::JP(_op) => todo!(),
+            Inst::JNP(_op) => todo!(),
+            Inst::JL(_op) => todo!(),
+            Inst::JNL(_op) => todo!(),
+            Inst::JLE(_op) => todo!(),
+            Inst::JNLE(_op) => todo!(),
+            Inst::LOOPNZ(_op) => todo!(),
+            Inst::LOOPZ(_op) => todo!(),
+            Inst::LOOP(op) => {
+                self.dec(Operand::Reg(Register::CX));
+                self.jump(op, self.get_value(Operand::Reg(Register::CX)) != 0);
+            }
+            Inst::JCXZ(_op) => todo!(),
+            // Memory is flat, so only ip decides where execution continues, but cs still follows
+            Inst::JMPF { segment, offset } => {
+                self.set_value(Operand::Reg(Register::CS), segment);
+                self.iptr = offset as usize;
+            }
+            Inst::CALLF { segment, offset } => {
+                self.push(self.regs.get_reg(Register::CS));
+                self.push(self.iptr as u16);
+
+                self.set_value(Operand::Reg(Register::CS), segment);
+                self.iptr = offset as usize;
+            }
+            // The return address is the instruction after the call, which iptr already points at
+            Inst::CALL(op) => {
+                self.push(self.iptr as u16);
+                self.jump(op, true);
+            }
+            Inst::RET(pop) => {
+                self.iptr = self.pop() as usize;
+
+                if let Some(pop) = pop {
+                    let sp = self.regs.get_reg(Register::SP).wrapping_add(pop);
+                    self.set_value(Operand::Reg(Register::SP), sp);
+                }
+            }
+            Inst::HLT => {}
+            // There's only ever one processor, so there's nothing to lock out, and an override
+            // without a memory operand has nothing to apply to
+            Inst::Prefixed(Prefix::Lock | Prefix::Segment(_), inst) => self.execute(*inst),
+            // Repeating needs the string ops, which aren't simulated yet
+            Inst::Prefixed(Prefix::Rep | Prefix::Repne, _) => self.halt_unsupported(&inst),
+        }
+    }
+
+    fn halt_unsupported(&mut self, inst: &Inst) {
+        self.trace(TraceMode::Registers, &format!(" ; {inst} isn't supported, halting"));
+        self.halted = true;
+    }
+
+    /// Decodes, traces and executes the next instruction, returning it. Returns None once the
+    /// program halts or reaches an unsupported instruction or bytes that can't be decoded, so callers can single-step, look at
+    /// the registers in between, or stop when `iptr` hits a breakpoint.
+    pub fn step(&mut self) -> Option<Inst> {
+        if self.halted {
+            return None;
+        }
+
+        let prev_iptr = self.iptr;
+        let inst = self.next_instr()?;
+
+        self.trace(TraceMode::Full, &inst.to_string());
+
+        self.estimate_cycles(&inst);
+
+        let change = self.radix.format_change("ip", prev_iptr, self.iptr);
+        self.trace(TraceMode::Full, &format!(" |{change}"));
+
+        if let Inst::HLT = inst {
+            self.trace(TraceMode::Registers, "\n");
+            return None;
+        }
+
+        self.execute(inst.clone());
+
+        self.trace(TraceMode::Registers, "\n");
+        if self.halted {
+            return None;
+        }
+
+        Some(inst)
+    }
+
+    /// Decodes `bytes` as a single instruction at the current ip and executes it, returning
+    /// everything it changed. Meant for testing instruction semantics in isolation.
+    pub fn execute_one(&mut self, bytes: &[u8]) -> StateDelta {
+        self.memory[self.iptr..self.iptr + bytes.len()].copy_from_slice(bytes);
+
+        let regs_before = WIDE_REGISTERS.map(|reg| self.regs.get_reg(reg));
+        let memory_before = self.memory;
+        let flags_before = self.flags;
+        let iptr_before = self.iptr;
+
+        let inst = self.next_instr().expect("Couldn't decode instruction");
+        self.execute(inst);
+
+        let regs = WIDE_REGISTERS
+            .into_iter()
+            .zip(regs_before)
+            .map(|(reg, before)| (reg, before, self.regs.get_reg(reg)))
+            .filter(|(_, before, after)| before != after)
+            .collect();
+
+        let memory = memory_before
+            .iter()
+            .zip(&self.memory)
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(addr, (before, after))| (addr, *before, *after))
+            .collect();
+
+        StateDelta {
+            regs,
+            flags: (flags_before, self.flags),
+            memory,
+            iptr: (iptr_before, self.iptr),
+        }
+    }
+
     fn ea_cycles(ea: EffAddr) -> u32 {
         use Register::*;
-        match ea {
-           EffAddr { base: None, index: None, offset: Some(_) } => 6,
 
-           EffAddr { base: None, index: Some(_), offset: None }
-           | EffAddr { base: Some(_), index: None, offset: None } => 5,
+        // A segment override costs an extra 2 clocks on top of the address calculation
+        let segment = if ea.segment.is_some() { 2 } else { 0 };
+
+        segment + match ea {
+           EffAddr { base: None, index: None, offset: Some(_), .. } => 6,
+
+           EffAddr { base: None, index: Some(_), offset: None, .. }
+           | EffAddr { base: Some(_), index: None, offset: None, .. } => 5,
 
-           EffAddr { base: None, index: Some(_), offset: Some(_) }
-           | EffAddr { base: Some(_), index: None, offset: Some(_) } => 9,
+           EffAddr { base: None, index: Some(_), offset: Some(_), .. }
+           | EffAddr { base: Some(_), index: None, offset: Some(_), .. } => 9,
 
-           EffAddr { base: Some(BP), index: Some(DI), offset: None }
-           | EffAddr { base: Some(BX), index: Some(SI), offset: None } => 7,
-           EffAddr { base: Some(BP), index: Some(SI), offset: None }
-           | EffAddr { base: Some(BX), index: Some(DI), offset: None } => 8,
+           EffAddr { base: Some(BP), index: Some(DI), offset: None, .. }
+           | EffAddr { base: Some(BX), index: Some(SI), offset: None, .. } => 7,
+           EffAddr { base: Some(BP), index: Some(SI), offset: None, .. }
+           | EffAddr { base: Some(BX), index: Some(DI), offset: None, .. } => 8,
 
-           EffAddr { base: Some(BP), index: Some(DI), offset: Some(_) }
-           | EffAddr { base: Some(BX), index: Some(SI), offset: Some(_) } => 11,
-           EffAddr { base: Some(BP), index: Some(SI), offset: Some(_) }
-           | EffAddr { base: Some(BX), index: Some(DI), offset: Some(_) } => 12,
+           EffAddr { base: Some(BP), index: Some(DI), offset: Some(_), .. }
+           | EffAddr { base: Some(BX), index: Some(SI), offset: Some(_), .. } => 11,
+           EffAddr { base: Some(BP), index: Some(SI), offset: Some(_), .. }
+           | EffAddr { base: Some(BX), index: Some(DI), offset: Some(_), .. } => 12,
 
            _ => panic!("Invalid EffAddr"),
         }
@@ -268,240 +628,1009 @@ impl State {
         }
     }
 
-    pub fn estimate_cycles(&mut self, inst: &Inst) {
+    /// Words an instruction moves to or from the stack, on top of any memory operand
+    fn stack_transfers(inst: &Inst) -> u32 {
+        match inst {
+            Inst::PUSH(_) | Inst::POP(_) | Inst::PUSHF | Inst::POPF => 1,
+            Inst::CALL(_) | Inst::RET(_) => 1,
+            // A far call pushes cs as well as ip
+            Inst::CALLF { .. } => 2,
+            _ => 0,
+        }
+    }
+
+    /// What `inst` would cost from the current state, without adding it to the running totals
+    fn cycle_estimate(&self, inst: &Inst) -> CycleEstimate {
         use Operand::*;
-        let (base_cycles, ea_cycles, penality_cycles) = match inst {
+
+        // Jumps are estimated before they execute, so these decide whether they'll be taken
+        let zero = self.is_set(Flag::Zero);
+        let sign = self.is_set(Flag::Signed);
+        let parity = self.is_set(Flag::Parity);
+        let cx = self.regs.get_reg(Register::CX);
+
+        let (base_cycles, mem) = match inst {
             Inst::MOV(op1, op2) => match (op1, op2) {
-                (Reg(_), ImmByte(_) | ImmWord(_)) => (4, 0, 0),
-                (Reg(_), Reg(_)) => (2, 0, 0),
-                (Reg(_), MemByte(ea) | MemWord(ea)) => {
-                    (8, Self::ea_cycles(*ea), self.transfer_penalty(1, *ea))
-                }
-                (MemByte(ea) | MemWord(ea), Reg(_)) => {
-                    (9, Self::ea_cycles(*ea), self.transfer_penalty(1, *ea))
-                }
-                _ => (0, 0, 0)
+                (Reg(_), ImmByte(_) | ImmWord(_)) => (4, None),
+                (Reg(_), Reg(_)) => (2, None),
+                (Reg(_), MemByte(ea) | MemWord(ea)) => (8, Some((*ea, 1))),
+                (MemByte(ea) | MemWord(ea), Reg(_)) => (9, Some((*ea, 1))),
+                (MemByte(ea) | MemWord(ea), ImmByte(_) | ImmWord(_)) => (10, Some((*ea, 1))),
+                _ => (0, None)
             },
-            Inst::ADD(op1, op2) => match (op1, op2) {
-                (Reg(_), ImmByte(_) | ImmWord(_)) => (4, 0, 0),
-                (Reg(_), Reg(_)) => (3, 0, 0),
-                (Reg(_), MemByte(ea) | MemWord(ea)) => {
-                    (9, Self::ea_cycles(*ea), self.transfer_penalty(1, *ea))
-                }
-                (MemByte(ea) | MemWord(ea), Reg(_)) => {
-                    (16, Self::ea_cycles(*ea), self.transfer_penalty(2, *ea))
+            // 8086 manual table 2-21: SUB, ADC, SBB and the logical ops share ADD's timings
+            Inst::ADD(op1, op2)
+            | Inst::SUB(op1, op2)
+            | Inst::ADC(op1, op2)
+            | Inst::SBB(op1, op2)
+            | Inst::OR(op1, op2)
+            | Inst::AND(op1, op2)
+            | Inst::XOR(op1, op2) => match (op1, op2) {
+                (Reg(_), ImmByte(_) | ImmWord(_)) => (4, None),
+                (Reg(_), Reg(_)) => (3, None),
+                (Reg(_), MemByte(ea) | MemWord(ea)) => (9, Some((*ea, 1))),
+                (MemByte(ea) | MemWord(ea), Reg(_)) => (16, Some((*ea, 2))),
+                (MemByte(ea) | MemWord(ea), ImmByte(_) | ImmWord(_)) => (17, Some((*ea, 2))),
+                _ => (0, None)
+            },
+            Inst::LEA(_, MemByte(ea) | MemWord(ea)) => (2, Some((*ea, 0))),
+            Inst::LEA(_, _) => (0, None),
+
+            Inst::INC(op) | Inst::DEC(op) => match op {
+                Reg(reg) if reg.is_wide() => (2, None),
+                Reg(_) => (3, None),
+                MemByte(ea) | MemWord(ea) => (15, Some((*ea, 2))),
+                _ => (0, None),
+            },
+            Inst::PUSH(op) => match op {
+                Reg(Register::ES | Register::CS | Register::SS | Register::DS) => (10, None),
+                Reg(_) => (11, None),
+                MemByte(ea) | MemWord(ea) => (16, Some((*ea, 1))),
+                _ => (0, None),
+            },
+            Inst::POP(op) => match op {
+                Reg(_) => (8, None),
+                MemByte(ea) | MemWord(ea) => (17, Some((*ea, 1))),
+                _ => (0, None),
+            },
+            Inst::PUSHF => (10, None),
+            Inst::POPF => (8, None),
+            Inst::MOVS { .. } => (0, None),
+            Inst::CMPS { .. } => (0, None),
+            Inst::SCAS { .. } => (0, None),
+            Inst::LODS { .. } => (0, None),
+            Inst::STOS { .. } => (0, None),
+            // Like ADD, except memory is only read, never written back
+            Inst::CMP(op1, op2) => match (op1, op2) {
+                (Reg(_), ImmByte(_) | ImmWord(_)) => (4, None),
+                (Reg(_), Reg(_)) => (3, None),
+                (Reg(_), MemByte(ea) | MemWord(ea)) | (MemByte(ea) | MemWord(ea), Reg(_)) => {
+                    (9, Some((*ea, 1)))
                 }
-                (MemByte(ea) | MemWord(ea), ImmByte(_) | ImmWord(_)) => {
-                    (17, Self::ea_cycles(*ea), self.transfer_penalty(2, *ea))
+                (MemByte(ea) | MemWord(ea), ImmByte(_) | ImmWord(_)) => (10, Some((*ea, 1))),
+                _ => (0, None)
+            },
+            Inst::TEST(op1, op2) => match (op1, op2) {
+                (Reg(Register::AL | Register::AX), ImmByte(_) | ImmWord(_)) => (4, None),
+                (Reg(_), ImmByte(_) | ImmWord(_)) => (5, None),
+                (Reg(_), Reg(_)) => (3, None),
+                (Reg(_), MemByte(ea) | MemWord(ea)) | (MemByte(ea) | MemWord(ea), Reg(_)) => {
+                    (9, Some((*ea, 1)))
                 }
-                _ => (0, 0, 0)
+                (MemByte(ea) | MemWord(ea), ImmByte(_) | ImmWord(_)) => (11, Some((*ea, 1))),
+                _ => (0, None)
             },
+            // Conditional jumps take 16 clocks when taken and 4 when not. Carry and overflow
+            // aren't tracked, so they're treated as clear.
+            Inst::JO(_) | Inst::JB(_) => (4, None),
+            Inst::JNO(_) | Inst::JNB(_) => (16, None),
+            Inst::JE(_) | Inst::JBE(_) => (if zero { 16 } else { 4 }, None),
+            Inst::JNE(_) | Inst::JNBE(_) => (if zero { 4 } else { 16 }, None),
+            Inst::JS(_) | Inst::JL(_) => (if sign { 16 } else { 4 }, None),
+            Inst::JNS(_) | Inst::JNL(_) => (if sign { 4 } else { 16 }, None),
+            Inst::JP(_) => (if parity { 16 } else { 4 }, None),
+            Inst::JNP(_) => (if parity { 4 } else { 16 }, None),
+            Inst::JLE(_) => (if zero || sign { 16 } else { 4 }, None),
+            Inst::JNLE(_) => (if zero || sign { 4 } else { 16 }, None),
+            // The loops decrement cx before checking it
+            Inst::LOOPNZ(_) => (if cx != 1 && !zero { 19 } else { 5 }, None),
+            Inst::LOOPZ(_) => (if cx != 1 && zero { 18 } else { 6 }, None),
+            Inst::LOOP(_) => (if cx != 1 { 17 } else { 5 }, None),
+            Inst::JCXZ(_) => (if cx == 0 { 18 } else { 6 }, None),
+            Inst::JMPF { .. } => (15, None),
+            Inst::CALLF { .. } => (28, None),
+            Inst::CALL(_) => (19, None),
+            Inst::RET(None) => (8, None),
+            Inst::RET(Some(_)) => (12, None),
+            Inst::HLT => (2, None),
+            // LOCK adds 2 clocks. A segment override with no memory operand has no address to
+            // cost, and the repeats cost nothing extra until the string ops are simulated.
+            Inst::Prefixed(prefix, inst) => {
+                let mut estimate = self.cycle_estimate(inst);
+                if let Prefix::Lock = prefix {
+                    estimate.base += 2;
+                }
 
-            Inst::SUB(_, _) => (0, 0, 0),
-            Inst::CMP(_, _) => (0, 0, 0),
-            Inst::JO(_) => (0, 0, 0),
-            Inst::JNO(_) => (0, 0, 0),
-            Inst::JB(_) => (0, 0, 0),
-            Inst::JNB(_) => (0, 0, 0),
-            Inst::JE(_) => (0, 0, 0),
-            Inst::JNE(_) => (0, 0, 0),
-            Inst::JBE(_) => (0, 0, 0),
-            Inst::JNBE(_) => (0, 0, 0),
-            Inst::JS(_) => (0, 0, 0),
-            Inst::JNS(_) => (0, 0, 0),
-            Inst::JP(_) => (0, 0, 0),
-            Inst::JNP(_) => (0, 0, 0),
-            Inst::JL(_) => (0, 0, 0),
-            Inst::JNL(_) => (0, 0, 0),
-            Inst::JLE(_) => (0, 0, 0),
-            Inst::JNLE(_) => (0, 0, 0),
-            Inst::LOOPNZ(_) => (0, 0, 0),
-            Inst::LOOPZ(_) => (0, 0, 0),
-            Inst::LOOP(_) => (0, 0, 0),
-            Inst::JCXZ(_) => (0, 0, 0),
-            Inst::HLT => (2, 0, 0),
+                return estimate;
+            }
         };
 
-        let cycles = base_cycles + ea_cycles + penality_cycles;
+        let mut estimate = match mem {
+            Some((ea, transfers)) => CycleEstimate {
+                base: base_cycles,
+                ea: Self::ea_cycles(ea),
+                penalty: self.transfer_penalty(transfers, ea),
+                transfers,
+            },
+            None => CycleEstimate { base: base_cycles, ..Default::default() },
+        };
+        estimate.transfers += Self::stack_transfers(inst);
+        estimate
+    }
+
+    pub fn estimate_cycles(&mut self, inst: &Inst) -> CycleEstimate {
+        let estimate = self.cycle_estimate(inst);
+
+        let cycles = estimate.total();
         self.cycles_estimate += cycles;
+        self.memory_transfers += estimate.transfers;
 
-        print!(" ; Clocks: +{cycles} = {}", self.cycles_estimate);
-        if ea_cycles > 0 || penality_cycles > 0 {
-            print!(" ({base_cycles}");
+        let mut clocks = format!(" ; Clocks: +{cycles} = {}", self.cycles_estimate);
+        if estimate.ea > 0 || estimate.penalty > 0 {
+            clocks += &format!(" ({}", estimate.base);
 
-            if ea_cycles > 0 {
-                print!(" + {ea_cycles}ea");
+            if estimate.ea > 0 {
+                clocks += &format!(" + {}ea", estimate.ea);
             }
 
-            if penality_cycles > 0 {
-                print!(" + {penality_cycles}p");
+            if estimate.penalty > 0 {
+                clocks += &format!(" + {}p", estimate.penalty);
             }
 
-            print!(")");
+            clocks += ")";
         }
+        self.trace(TraceMode::Full, &clocks);
+
+        estimate
+    }
+}
+
+/// Clocks for a single instruction, along with how many memory reads and writes it performs
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CycleEstimate {
+    pub base: u32,
+    pub ea: u32,
+    pub penalty: u32,
+    pub transfers: u32,
+}
+
+impl CycleEstimate {
+    pub fn total(&self) -> u32 {
+        self.base + self.ea + self.penalty
     }
 }
 
 pub fn exec(binary: Vec<u8>) -> State {
+    exec_with_radix(binary, Radix::default())
+}
+
+pub fn exec_with_radix(binary: Vec<u8>, radix: Radix) -> State {
     let mut state = State::new(&binary);
+    state.radix = radix;
 
-    let mut prev_iptr = 0;
-    while let Some(inst) = state.next_instr() {
-        print!("{inst}");
+    while state.step().is_some() {}
 
-        state.estimate_cycles(&inst);
+    return state;
+}
 
-        print!(" | ip:0x{prev_iptr:x}->0x{:x}", state.iptr);
-        prev_iptr = state.iptr;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugCommand {
+    Step(usize),
+    Continue,
+    Regs,
+    Mem { addr: usize, len: usize },
+    Quit,
+}
 
-        match inst {
-            Inst::MOV(op1, op2) => state.set_value(op1, state.get_value(op2)),
-            Inst::ADD(op1, op2) => {
-                let add = state.get_value(op1) + state.get_value(op2);
-                state.set_value(op1, add);
-                state.update_flags_from_value(add);
+impl FromStr for DebugCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_num = |num: &str| {
+            let parsed = match num.strip_prefix("0x") {
+                Some(hex) => usize::from_str_radix(hex, 16),
+                None => num.parse(),
+            };
+
+            parsed.map_err(|_| format!("Invalid number '{num}'"))
+        };
+
+        let mut words = s.split_whitespace();
+        let cmd = match words.next().unwrap_or("step") {
+            "s" | "step" => Self::Step(words.next().map_or(Ok(1), parse_num)?),
+            "c" | "continue" => Self::Continue,
+            "r" | "regs" => Self::Regs,
+            "m" | "mem" => {
+                let addr = words.next().ok_or("Expected an address after 'mem'")?;
+                Self::Mem {
+                    addr: parse_num(addr)?,
+                    len: words.next().map_or(Ok(16), parse_num)?,
+                }
             }
-            Inst::SUB(op1, op2) => {
-                let sub = state.get_value(op1).wrapping_sub(state.get_value(op2));
-                state.set_value(op1, sub);
-                state.update_flags_from_value(sub);
+            "q" | "quit" => Self::Quit,
+            other => return Err(format!("Unknown command '{other}'")),
+        };
+
+        Ok(cmd)
+    }
+}
+
+/// Runs `state` one command at a time, reading `step [n]`, `continue`, `regs`, `mem <addr> [len]`
+/// and `quit` from `commands`. An empty line steps once. Returns once the program halts, or on
+/// `quit` or the end of input.
+pub fn debug<R: BufRead>(state: &mut State, commands: R) {
+    let mut lines = commands.lines();
+
+    loop {
+        print!("> ");
+        stdout().flush().unwrap();
+
+        let Some(Ok(line)) = lines.next() else {
+            return;
+        };
+
+        let cmd = match line.parse::<DebugCommand>() {
+            Ok(cmd) => cmd,
+            Err(err) => {
+                println!("{err}");
+                continue;
             }
-            Inst::CMP(op1, op2) => {
-                let sub = state.get_value(op1).wrapping_sub(state.get_value(op2));
-                state.update_flags_from_value(sub);
+        };
+
+        match cmd {
+            DebugCommand::Step(n) => {
+                for _ in 0..n {
+                    if state.step().is_none() {
+                        return;
+                    }
+                }
             }
-            Inst::JO(_op) => todo!(),
-            Inst::JNO(_op) => todo!(),
-            Inst::JB(_op) => todo!(),
-            Inst::JNB(_op) => todo!(),
-            Inst::JE(_op) => todo!(),
-            Inst::JNE(op) => state.jump(op, !state.is_set(Flag::Zero)),
-            Inst::JBE(_op) => todo!(),
-            Inst::JNBE(_op) => todo!(),
-            Inst::JS(_op) => todo!(),
-            Inst::JNS(_op) => todo!(),
-            Inst::JP(_op) => todo!(),
-            Inst::JNP(_op) => todo!(),
-            Inst::JL(_op) => todo!(),
-            Inst::JNL(_op) => todo!(),
-            Inst::JLE(_op) => todo!(),
-            Inst::JNLE(_op) => todo!(),
-            Inst::LOOPNZ(_op) => todo!(),
-            Inst::LOOPZ(_op) => todo!(),
-            Inst::LOOP(op) => {
-                state.dec(Operand::Reg(Register::CX));
-                state.jump(op, state.get_value(Operand::Reg(Register::CX)) != 0);
+            DebugCommand::Continue => {
+                while state.step().is_some() {}
+                return;
             }
-            Inst::JCXZ(_op) => todo!(),
-            Inst::HLT => {
-                println!();
-                break;
+            DebugCommand::Regs => {
+                for reg in WIDE_REGISTERS {
+                    println!("{reg}: {}", state.radix.format(state.regs.get_reg(reg) as usize));
+                }
+                println!("ip: {}", state.radix.format(state.iptr));
+                println!("flags: {}", state.flags_as_string());
             }
+            DebugCommand::Mem { addr, len } => {
+                for (row, chunk) in state.dump_region(addr, len).chunks(16).enumerate() {
+                    let bytes = chunk.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>();
+                    println!("{:04x}: {}", addr + row * 16, bytes.join(" "));
+                }
+            }
+            DebugCommand::Quit => return,
         }
-
-        println!();
     }
-
-    return state;
 }
 
 pub fn exec_file(path: &str) -> State {
     let asm = std::fs::read_to_string(path).expect("Failed to read test file");
-    println!("{}", asm);
-    let binary = assemble(&asm);
+    let binary = assemble_or_fixture(&asm, fixture_path(path));
     exec(binary)
 }
 
+/// The known final state of a homework listing after execution
+pub struct Homework {
+    pub listing: &'static str,
+    pub regs: &'static [(Register, u16)],
+    pub iptr: Option<usize>,
+    pub flags: Option<&'static str>,
+    pub cycles: Option<u32>,
+}
+
+impl Homework {
+    pub fn passes(&self, state: &State) -> bool {
+        self.regs
+            .iter()
+            .all(|(reg, val)| state.get_value(Operand::Reg(*reg)) == *val)
+            && self.iptr.is_none_or(|iptr| state.iptr == iptr)
+            && self.flags.is_none_or(|flags| state.flags_as_string() == flags)
+            && self.cycles.is_none_or(|cycles| state.cycles_estimate == cycles)
+    }
+}
+
+pub const HOMEWORK: [Homework; 10] = {
+    use Register::*;
+
+    [
+        Homework {
+            listing: "inputs/listing_0043_immediate_movs.asm",
+            regs: &[(AX, 1), (BX, 2), (CX, 3), (DX, 4), (SP, 5), (BP, 6), (SI, 7), (DI, 8)],
+            iptr: None,
+            flags: None,
+            cycles: None,
+        },
+        Homework {
+            listing: "inputs/listing_0044_register_movs.asm",
+            regs: &[(AX, 4), (BX, 3), (CX, 2), (DX, 1), (SP, 1), (BP, 2), (SI, 3), (DI, 4)],
+            iptr: None,
+            flags: None,
+            cycles: None,
+        },
+        Homework {
+            listing: "inputs/listing_0046_add_sub_cmp.asm",
+            regs: &[(BX, 0xe102), (CX, 0x0f01), (SP, 0x03e6)],
+            iptr: None,
+            flags: Some("PZ"),
+            cycles: Some(32),
+        },
+        Homework {
+            listing: "inputs/listing_0048_ip_register.asm",
+            regs: &[(BX, 0x07d0), (CX, 0xfce0)],
+            iptr: Some(0x000f),
+            flags: Some("S"),
+            cycles: None,
+        },
+        Homework {
+            listing: "inputs/listing_0049_conditional_jumps.asm",
+            regs: &[(BX, 0x0406)],
+            iptr: Some(0x000f),
+            flags: Some("PZ"),
+            cycles: Some(70),
+        },
+        Homework {
+            listing: "inputs/listing_0051_memory_mov.asm",
+            regs: &[(BX, 1), (CX, 2), (DX, 10), (BP, 4)],
+            iptr: None,
+            flags: None,
+            cycles: None,
+        },
+        Homework {
+            listing: "inputs/listing_0052_memory_add_loop.asm",
+            regs: &[(BX, 6)],
+            iptr: None,
+            flags: None,
+            cycles: None,
+        },
+        Homework {
+            listing: "inputs/listing_0053_add_loop_challenge.asm",
+            regs: &[(BX, 6)],
+            iptr: None,
+            flags: None,
+            cycles: None,
+        },
+        Homework {
+            listing: "inputs/listing_0056_estimating_cycles.asm",
+            regs: &[],
+            iptr: None,
+            flags: None,
+            cycles: Some(194),
+        },
+        Homework {
+            listing: "inputs/listing_0057_challenge_cycles.asm",
+            regs: &[],
+            iptr: None,
+            flags: None,
+            cycles: Some(291),
+        },
+    ]
+};
+
+/// Executes every homework listing, returning whether each one ended in its expected state
+pub fn run_all_homework() -> Vec<(&'static str, bool)> {
+    HOMEWORK
+        .iter()
+        .map(|hw| (hw.listing, hw.passes(&exec_file(hw.listing))))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::exec_file;
-    use crate::parse::Operand::*;
-    use crate::parse::Register::*;
+    use super::{
+        debug, exec, parity_of, TraceMode, exec_file, run_all_homework, DebugCommand, Flag, Radix, State, StateDelta,
+        HOMEWORK, MEM_SIZE,
+    };
+    use crate::{
+        assemble_or_fixture, assert_memory_eq, fixture_path,
+        parse::{Inst, Operand::*, Register},
+    };
+
+    fn assert_homework(listing: &str) {
+        let hw = HOMEWORK
+            .iter()
+            .find(|hw| hw.listing.ends_with(listing))
+            .unwrap_or_else(|| panic!("No homework entry for {listing}"));
+
+        let state = exec_file(hw.listing);
+
+        for (reg, val) in hw.regs {
+            assert_eq!(state.get_value(Reg(*reg)), *val, "{reg} after {listing}");
+        }
+
+        if let Some(iptr) = hw.iptr {
+            assert_eq!(state.iptr, iptr, "ip after {listing}");
+        }
+
+        if let Some(flags) = hw.flags {
+            assert_eq!(state.flags_as_string(), flags, "flags after {listing}");
+        }
+
+        if let Some(cycles) = hw.cycles {
+            assert_eq!(state.cycles_estimate, cycles, "cycles after {listing}");
+        }
+    }
 
     #[test]
     fn test_hw4() {
         println!("Exec imm moves:\n");
-        let state = exec_file("inputs/listing_0043_immediate_movs.asm");
+        assert_homework("listing_0043_immediate_movs.asm");
 
-        assert_eq!(state.get_value(Reg(AX)), 1);
-        assert_eq!(state.get_value(Reg(BX)), 2);
-        assert_eq!(state.get_value(Reg(CX)), 3);
-        assert_eq!(state.get_value(Reg(DX)), 4);
+        println!("\nExec reg moves:\n");
+        assert_homework("listing_0044_register_movs.asm");
+    }
 
-        assert_eq!(state.get_value(Reg(SP)), 5);
-        assert_eq!(state.get_value(Reg(BP)), 6);
-        assert_eq!(state.get_value(Reg(SI)), 7);
-        assert_eq!(state.get_value(Reg(DI)), 8);
+    #[test]
+    fn test_hw5() {
+        assert_homework("listing_0046_add_sub_cmp.asm");
+    }
 
-        println!("\nExec reg moves:\n");
-        let state = exec_file("inputs/listing_0044_register_movs.asm");
+    #[test]
+    fn test_hw6() {
+        assert_homework("listing_0048_ip_register.asm");
+        assert_homework("listing_0049_conditional_jumps.asm");
+    }
 
-        assert_eq!(state.get_value(Reg(AX)), 4);
-        assert_eq!(state.get_value(Reg(BX)), 3);
-        assert_eq!(state.get_value(Reg(CX)), 2);
-        assert_eq!(state.get_value(Reg(DX)), 1);
+    #[test]
+    fn test_hw7() {
+        assert_homework("listing_0051_memory_mov.asm");
+        assert_homework("listing_0052_memory_add_loop.asm");
+        assert_homework("listing_0053_add_loop_challenge.asm");
+    }
 
-        assert_eq!(state.get_value(Reg(SP)), 1);
-        assert_eq!(state.get_value(Reg(BP)), 2);
-        assert_eq!(state.get_value(Reg(SI)), 3);
-        assert_eq!(state.get_value(Reg(DI)), 4);
+    #[test]
+    fn test_hw8() {
+        assert_homework("listing_0056_estimating_cycles.asm");
+        assert_homework("listing_0057_challenge_cycles.asm");
     }
 
     #[test]
-    fn test_hw5() {
-        let state = exec_file("inputs/listing_0046_add_sub_cmp.asm");
+    fn test_memory_transfers() {
+        // 1 direct read, 8 reads/writes through registers, and a read-modify-write ADD
+        let state = exec_file("inputs/listing_0056_estimating_cycles.asm");
+        assert_eq!(state.memory_transfers, 11);
 
-        assert_eq!(state.get_value(Reg(BX)), 0xe102);
-        assert_eq!(state.get_value(Reg(CX)), 0x0f01);
-        assert_eq!(state.get_value(Reg(SP)), 0x03e6);
+        // 8 MOVs, 2 ADDs reading memory, and 3 read-modify-write ADDs
+        let state = exec_file("inputs/listing_0057_challenge_cycles.asm");
+        assert_eq!(state.memory_transfers, 16);
+
+        // mov word [bx], 5 writes once, and costs 10 plus the address calculation
+        let mut state = State::new(&[]);
+        let (_, mov) = Inst::from_encoding(&[0xc7, 0x07, 0x05, 0x00]).unwrap();
+        let estimate = state.estimate_cycles(&mov);
+        assert_eq!(estimate.transfers, 1);
+        assert_eq!(estimate.total(), 10 + 5);
+        assert_eq!(state.memory_transfers, 1);
+    }
 
-        assert_eq!(state.flags_as_string(), "PZ");
+    #[test]
+    fn test_all_homework() {
+        let results = run_all_homework();
+
+        assert_eq!(results.len(), HOMEWORK.len());
+        for (listing, passed) in results {
+            assert!(passed, "{listing} did not end in its expected state");
+        }
     }
 
     #[test]
-    fn test_hw6() {
-        let state = exec_file("inputs/listing_0048_ip_register.asm");
+    fn test_execute_one() {
+        let mut state = State::new(&[]);
+        state.set_value(Reg(Register::AX), 0x7fff);
+
+        // add ax, 1
+        let delta = state.execute_one(&[0x05, 0x01, 0x00]);
+
+        let expected = StateDelta {
+            regs: vec![(Register::AX, 0x7fff, 0x8000)],
+            flags: (0, Flag::Parity as u16 | Flag::Signed as u16),
+            memory: vec![],
+            iptr: (0, 3),
+        };
+        assert_eq!(delta, expected);
+
+        // mov [bx + 64], ax
+        let delta = state.execute_one(&[0x89, 0x47, 0x40]);
+        assert_eq!(delta.regs, vec![]);
+        assert_eq!(delta.memory, vec![(0x41, 0, 0x80)]);
+        assert_eq!(delta.iptr, (3, 6));
+    }
 
-        assert_eq!(state.get_value(Reg(BX)), 0x07d0);
-        assert_eq!(state.get_value(Reg(CX)), 0xfce0);
-        assert_eq!(state.iptr, 0x000f);
+    #[test]
+    fn test_exec_lea() {
+        let mut state = State::new(&[]);
+        state.set_value(Reg(Register::BP), 0x100);
+        state.set_value(Reg(Register::SI), 0x20);
+
+        // lea bx, [bp + si + 4]
+        let delta = state.execute_one(&[0x8d, 0x5a, 0x04]);
+        assert_eq!(delta.regs, vec![(Register::BX, 0, 0x124)]);
+        assert_eq!(delta.memory, vec![]);
+        assert_eq!(delta.flags, (0, 0));
+        assert_eq!(delta.iptr, (0, 3));
+
+        // lea bx, [bp + si + 4] costs 2 plus the address calculation
+        let (_, lea) = Inst::from_encoding(&[0x8d, 0x5a, 0x04]).unwrap();
+        assert_eq!(state.estimate_cycles(&lea).total(), 2 + 12);
+    }
 
-        assert_eq!(state.flags_as_string(), "S");
+    #[test]
+    fn test_exec_push_pop() {
+        let mut state = State::new(&[]);
+        state.set_value(Reg(Register::SP), 0x100);
+        state.set_value(Reg(Register::AX), 1);
+        state.set_value(Reg(Register::BX), 2);
+        state.set_value(Reg(Register::CX), 3);
+
+        // push ax; push bx; push cx
+        for push in [0x50, 0x53, 0x51] {
+            state.execute_one(&[push]);
+        }
+        assert_eq!(state.get_value(Reg(Register::SP)), 0xfa);
+        assert_eq!(state.dump_region(0xfa, 6), [3, 0, 2, 0, 1, 0]);
+
+        // pop ax; pop bx; pop cx, so ax and cx swap
+        let delta = state.execute_one(&[0x58]);
+        assert_eq!(delta.regs, vec![(Register::AX, 1, 3), (Register::SP, 0xfa, 0xfc)]);
+        assert_eq!(delta.memory, vec![]);
+
+        state.execute_one(&[0x5b]);
+        state.execute_one(&[0x59]);
+
+        assert_eq!(state.get_value(Reg(Register::AX)), 3);
+        assert_eq!(state.get_value(Reg(Register::BX)), 2);
+        assert_eq!(state.get_value(Reg(Register::CX)), 1);
+        assert_eq!(state.get_value(Reg(Register::SP)), 0x100);
+
+        // push ax; pop ds
+        state.execute_one(&[0x50]);
+        let delta = state.execute_one(&[0x1f]);
+        assert_eq!(delta.regs, vec![(Register::SP, 0xfe, 0x100), (Register::DS, 0, 3)]);
+
+        // Each one moves a word through the stack, and push [bx] reads another from memory
+        let cases: [(&[u8], u32); 5] =
+            [(&[0x50], 1), (&[0x1f], 1), (&[0x9c], 1), (&[0x9d], 1), (&[0xff, 0x37], 2)];
+        for (encoding, transfers) in cases {
+            let (_, inst) = Inst::from_encoding(encoding).unwrap();
+            assert_eq!(state.estimate_cycles(&inst).transfers, transfers, "{inst}");
+        }
+    }
 
-        let state = exec_file("inputs/listing_0049_conditional_jumps.asm");
+    #[test]
+    fn test_parity_of() {
+        assert!(parity_of(0x00));
+        assert!(!parity_of(0x01));
+        assert!(parity_of(0xff));
+        assert!(parity_of(0x03));
+        assert!(!parity_of(0x80));
+    }
+
+    #[test]
+    fn test_parity_low_byte_only() {
+        let mut state = State::new(&[]);
+
+        // mov ax, 0x0100; add ax, 0 has an odd high byte, but the low byte is zero
+        state.execute_one(&[0xb8, 0x00, 0x01]);
+        state.execute_one(&[0x05, 0x00, 0x00]);
+        assert!(state.is_set(Flag::Parity));
+
+        // mov ax, 0xff01; add ax, 0
+        state.execute_one(&[0xb8, 0x01, 0xff]);
+        state.execute_one(&[0x05, 0x00, 0x00]);
+        assert!(!state.is_set(Flag::Parity));
+    }
+
+    #[test]
+    fn test_exec_flags_by_width() {
+        let mut state = State::new(&[]);
+
+        // add al, 1 takes 0x7f to 0x80, which is negative as a byte
+        state.set_value(Reg(Register::AX), 0x7f);
+        state.execute_one(&[0x04, 0x01]);
+        assert_eq!(state.get_value(Reg(Register::AL)), 0x80);
+        assert!(state.is_set(Flag::Signed));
+
+        // add ax, 1 gives the same 0x80, but as a word it's positive
+        state.set_value(Reg(Register::AX), 0x7f);
+        state.execute_one(&[0x05, 0x01, 0x00]);
+        assert_eq!(state.get_value(Reg(Register::AX)), 0x80);
+        assert!(!state.is_set(Flag::Signed));
+
+        state.set_value(Reg(Register::AX), 0x7fff);
+        state.execute_one(&[0x05, 0x01, 0x00]);
+        assert!(state.is_set(Flag::Signed));
+
+        // add al, 1 wraps 0xff to zero without touching ah
+        state.set_value(Reg(Register::AX), 0x01ff);
+        state.execute_one(&[0x04, 0x01]);
+        assert_eq!(state.get_value(Reg(Register::AX)), 0x0100);
+        assert!(state.is_set(Flag::Zero));
+        assert!(state.is_set(Flag::Parity));
+        assert!(!state.is_set(Flag::Signed));
+
+        // add al, 0 only sees the low byte of 0x8000
+        state.set_value(Reg(Register::AX), 0x8000);
+        state.execute_one(&[0x04, 0x00]);
+        assert!(!state.is_set(Flag::Signed));
+        assert!(state.is_set(Flag::Zero));
+    }
+
+    #[test]
+    fn test_exec_call_ret() {
+        let binary = vec![
+            0xbc, 0x00, 0x01, // mov sp, 256
+            0xb8, 0x01, 0x00, // mov ax, 1
+            0xe8, 0x03, 0x00, // call add_five
+            0x89, 0xc3, // mov bx, ax
+            0xf4, // hlt
+            0x83, 0xc0, 0x05, // add_five: add ax, 5
+            0xc3, // ret
+        ];
+
+        let state = exec(binary);
+        assert_eq!(state.get_value(Reg(Register::AX)), 6);
+        assert_eq!(state.get_value(Reg(Register::BX)), 6);
+        assert_eq!(state.get_value(Reg(Register::SP)), 0x100);
+        assert_eq!(state.dump_region(0xfe, 2), [0x09, 0x00]);
+        assert_eq!(state.iptr, 12);
+    }
 
-        assert_eq!(state.get_value(Reg(BX)), 0x0406);
-        assert_eq!(state.iptr, 0x000f);
+    #[test]
+    fn test_exec_ret_imm() {
+        let mut state = State::new(&[]);
+        state.set_value(Reg(Register::SP), 0xfc);
+        state.memory[0xfc..0xfe].copy_from_slice(&[0x20, 0x00]);
+
+        // ret 4
+        let delta = state.execute_one(&[0xc2, 0x04, 0x00]);
+        assert_eq!(delta.iptr, (0, 0x20));
+        assert_eq!(state.get_value(Reg(Register::SP)), 0x102);
+
+        // The return address is a single word on the stack
+        let (_, ret) = Inst::from_encoding(&[0xc2, 0x04, 0x00]).unwrap();
+        assert_eq!(state.estimate_cycles(&ret).transfers, 1);
+        let (_, call) = Inst::from_encoding(&[0xe8, 0x03, 0x00]).unwrap();
+        assert_eq!(state.estimate_cycles(&call).transfers, 1);
+    }
 
+    #[test]
+    fn test_exec_pushf_popf() {
+        let mut state = State::new(&[]);
+        state.set_value(Reg(Register::SP), 0x100);
+        state.set_value(Reg(Register::BX), 0x40);
+
+        // cmp bx, bx sets PZ, then pushf
+        state.execute_one(&[0x39, 0xdb]);
+        let delta = state.execute_one(&[0x9c]);
+        let saved = Flag::Parity as u16 | Flag::Zero as u16;
+        assert_eq!(delta.memory, vec![(0xfe, 0, saved as u8)]);
+
+        // add bx, bx clears them, popf brings them back
+        state.execute_one(&[0x01, 0xdb]);
+        assert_eq!(state.flags_as_string(), "");
+
+        let delta = state.execute_one(&[0x9d]);
+        assert_eq!(delta.flags, (0, saved));
         assert_eq!(state.flags_as_string(), "PZ");
+        assert_eq!(state.get_value(Reg(Register::SP)), 0x100);
+
+        // push word [bx], pop word [bx + 2]
+        state.memory[0x80..0x82].copy_from_slice(&[0x34, 0x12]);
+        state.execute_one(&[0xff, 0x37]);
+        state.execute_one(&[0x8f, 0x47, 0x02]);
+        assert_eq!(state.dump_region(0x80, 4), [0x34, 0x12, 0x34, 0x12]);
+
+        state.set_value(Reg(Register::SP), 0x100);
+        // push sp stores sp after it's decremented
+        state.execute_one(&[0x54]);
+        assert_eq!(state.dump_region(0xfe, 2), [0xfe, 0x00]);
     }
 
     #[test]
-    fn test_hw7() {
+    fn test_segment_override_cycles() {
+        let mut state = State::new(&[]);
+
+        // mov ax, [bx] and mov ax, cs:[bx]
+        let (_, plain) = Inst::from_encoding(&[0x8b, 0x07]).unwrap();
+        let (_, overridden) = Inst::from_encoding(&[0x2e, 0x8b, 0x07]).unwrap();
+
+        assert_eq!(state.estimate_cycles(&plain).total(), 8 + 5);
+        assert_eq!(state.estimate_cycles(&overridden).total(), 8 + 5 + 2);
+    }
+
+    #[test]
+    fn test_prefixed_cycles() {
+        let mut state = State::new(&[]);
+
+        // lock add [bx], ax costs the add plus 2 for the lock
+        let (_, locked) = Inst::from_encoding(&[0xf0, 0x01, 0x07]).unwrap();
+        let estimate = state.estimate_cycles(&locked);
+        assert_eq!(estimate.total(), 16 + 5 + 2);
+        assert_eq!(estimate.transfers, 2);
+
+        // ss mov ax, bx costs the same as the plain mov
+        let (_, overridden) = Inst::from_encoding(&[0x36, 0x89, 0xd8]).unwrap();
+        assert_eq!(state.estimate_cycles(&overridden).total(), 2);
+
+        // lock push ax still moves a word through the stack
+        let (_, locked) = Inst::from_encoding(&[0xf0, 0x50]).unwrap();
+        assert_eq!(state.estimate_cycles(&locked).transfers, 1);
+    }
+
+    #[test]
+    fn test_exec_inc_dec() {
+        let mut state = State::new(&[]);
+        state.set_value(Reg(Register::BX), 64);
+        state.set_value(Reg(Register::CX), 1);
+
+        // lock inc word [bx]
+        let delta = state.execute_one(&[0xf0, 0xff, 0x07]);
+        assert_eq!(delta.memory, vec![(64, 0, 1)]);
+        assert_eq!(delta.iptr, (0, 3));
+
+        // dec cx
+        let delta = state.execute_one(&[0x49]);
+        assert_eq!(delta.regs, vec![(Register::CX, 1, 0)]);
+        assert!(state.is_set(Flag::Zero));
+    }
+
+    #[test]
+    fn test_memory_mov_dump() {
         let state = exec_file("inputs/listing_0051_memory_mov.asm");
 
-        assert_eq!(state.get_value(Reg(BX)), 1);
-        assert_eq!(state.get_value(Reg(CX)), 2);
-        assert_eq!(state.get_value(Reg(DX)), 10);
-        assert_eq!(state.get_value(Reg(BP)), 4);
+        assert_memory_eq(&state, 1000, &[1, 0, 2, 0, 10, 0, 4, 0]);
+        assert_eq!(state.dump_region(1008, 4), [0; 4]);
+        assert_eq!(state.dump_region(MEM_SIZE - 2, 8).len(), 2);
+    }
+
+    #[test]
+    fn test_exec_test() {
+        let mut state = State::new(&[]);
+        state.set_value(Reg(Register::AX), 0x1234);
+
+        // test ax, 0
+        let delta = state.execute_one(&[0xa9, 0x00, 0x00]);
+        assert_eq!(delta.regs, vec![]);
+        assert!(state.is_set(Flag::Zero));
+
+        // test ax, 0x1000
+        let delta = state.execute_one(&[0xa9, 0x00, 0x10]);
+        assert_eq!(delta.regs, vec![]);
+        assert!(!state.is_set(Flag::Zero));
+        assert!(!state.is_set(Flag::Signed));
+    }
+
+    #[test]
+    fn test_exec_logical() {
+        let mut state = State::new(&[]);
+        state.set_value(Reg(Register::AX), 0x1234);
+        state.set_value(Reg(Register::BX), 0x00ff);
+
+        // and ax, bx
+        let delta = state.execute_one(&[0x21, 0xd8]);
+        assert_eq!(delta.regs, vec![(Register::AX, 0x1234, 0x0034)]);
+
+        // or ax, 0x8000
+        let delta = state.execute_one(&[0x0d, 0x00, 0x80]);
+        assert_eq!(delta.regs, vec![(Register::AX, 0x0034, 0x8034)]);
+        assert!(state.is_set(Flag::Signed));
+
+        // xor ax, ax
+        let delta = state.execute_one(&[0x31, 0xc0]);
+        assert_eq!(delta.regs, vec![(Register::AX, 0x8034, 0)]);
+        assert!(state.is_set(Flag::Zero));
+        assert!(!state.is_set(Flag::Signed));
+    }
 
-        let state = exec_file("inputs/listing_0052_memory_add_loop.asm");
+    #[test]
+    fn test_exec_far_jumps() {
+        let mut state = State::new(&[]);
+        state.set_value(Reg(Register::SP), 0x100);
+        state.set_value(Reg(Register::CS), 0x1111);
+
+        // call 0x2222:0x0040
+        let delta = state.execute_one(&[0x9a, 0x40, 0x00, 0x22, 0x22]);
+        assert_eq!(delta.iptr, (0, 0x40));
+        assert_eq!(
+            delta.regs,
+            vec![(Register::SP, 0x100, 0xfc), (Register::CS, 0x1111, 0x2222)]
+        );
+        // The return address is the ip after the call, with cs above it
+        assert_eq!(state.dump_region(0xfc, 4), [0x05, 0x00, 0x11, 0x11]);
+
+        let (_, callf) = Inst::from_encoding(&[0x9a, 0x40, 0x00, 0x22, 0x22]).unwrap();
+        assert_eq!(state.estimate_cycles(&callf).transfers, 2);
+
+        // jmp 0x3333:0x0010
+        let delta = state.execute_one(&[0xea, 0x10, 0x00, 0x33, 0x33]);
+        assert_eq!(delta.iptr, (0x40, 0x10));
+        assert_eq!(delta.regs, vec![(Register::CS, 0x2222, 0x3333)]);
+    }
 
-        assert_eq!(state.get_value(Reg(BX)), 6);
+    #[test]
+    fn test_exec_adc_sbb_halt() {
+        // adc ax, 5 and sbb ax, cx
+        let cases: [&[u8]; 2] = [&[0x15, 0x05, 0x00], &[0x19, 0xc8]];
+        for adc_or_sbb in cases {
+            let binary = [&[0xb8, 0x01, 0x00], adc_or_sbb, &[0xbb, 0x02, 0x00]].concat();
+
+            let mut state = State::new(&binary);
+            state.trace_mode = TraceMode::Registers;
+            state.capture_trace();
+            while state.step().is_some() {}
+
+            // mov ax, 1 runs, then it halts before mov bx, 2, with nothing changed by the adc/sbb
+            assert_eq!(state.get_value(Reg(Register::AX)), 1);
+            assert_eq!(state.get_value(Reg(Register::BX)), 0);
+            assert!(state.take_trace().contains("isn't supported"));
+        }
+    }
 
-        let state = exec_file("inputs/listing_0053_add_loop_challenge.asm");
+    #[test]
+    fn test_exec_unsupported_halts() {
+        let binary = [
+            0xb8, 0x01, 0x00, // mov ax, 1
+            0xf3, 0xa4, // rep movsb
+            0xbb, 0x02, 0x00, // mov bx, 2
+        ];
+
+        let mut state = State::new(&binary);
+        state.trace_mode = TraceMode::Registers;
+        state.capture_trace();
+        while state.step().is_some() {}
+
+        assert_eq!(state.get_value(Reg(Register::AX)), 1);
+        assert_eq!(state.get_value(Reg(Register::BX)), 0);
+        assert!(state.take_trace().contains("rep movsb isn't supported"));
+        assert!(state.step().is_none());
+
+        // On its own, a string op leaves everything but ip alone
+        let delta = state.execute_one(&[0xa4]);
+        assert_eq!(delta.regs, vec![]);
+        assert_eq!(delta.memory, vec![]);
+    }
+
+    #[test]
+    fn test_exec_sign_extended_immediate() {
+        let mut state = State::new(&[]);
+        state.set_value(Reg(Register::AX), 0xff80);
 
-        assert_eq!(state.get_value(Reg(BX)), 6);
+        // cmp ax, -128
+        state.execute_one(&[0x83, 0xf8, 0x80]);
+        assert!(state.is_set(Flag::Zero));
+
+        // add ax, -1
+        let delta = state.execute_one(&[0x83, 0xc0, 0xff]);
+        assert_eq!(delta.regs, vec![(Register::AX, 0xff80, 0xff7f)]);
     }
 
     #[test]
-    fn test_hw8() {
-        let state = exec_file("inputs/listing_0056_estimating_cycles.asm");
+    fn test_step_listing_0043() {
+        let path = "inputs/listing_0043_immediate_movs.asm";
+        let asm = std::fs::read_to_string(path).unwrap();
+        let mut state = State::new(&assemble_or_fixture(&asm, fixture_path(path)));
+
+        let expected = [
+            (Register::AX, 1),
+            (Register::BX, 2),
+            (Register::CX, 3),
+            (Register::DX, 4),
+            (Register::SP, 5),
+            (Register::BP, 6),
+            (Register::SI, 7),
+            (Register::DI, 8),
+        ];
+
+        for (i, (reg, val)) in expected.into_iter().enumerate() {
+            let inst = state.step().expect("Listing ended early");
+            assert_eq!(inst.to_string(), format!("mov {reg}, {val}"));
+            assert_eq!(state.iptr, 3 * (i + 1));
+
+            // Everything not yet reached is still zero
+            for (later, _) in &expected[i + 1..] {
+                assert_eq!(state.get_value(Reg(*later)), 0);
+            }
+            assert_eq!(state.get_value(Reg(reg)), val);
+        }
+
+        assert!(state.step().is_none());
+    }
 
-        assert_eq!(state.cycles_estimate, 194);
+    #[test]
+    fn test_trace_modes() {
+        // mov cx, 3; add cx, 2; hlt
+        let binary = [0xb9, 0x03, 0x00, 0x83, 0xc1, 0x02];
 
-        let state = exec_file("inputs/listing_0057_challenge_cycles.asm");
+        let trace = |mode| {
+            let mut state = State::new(&binary);
+            state.trace_mode = mode;
+            state.capture_trace();
+
+            while state.step().is_some() {}
+            state.take_trace()
+        };
+
+        assert_eq!(trace(TraceMode::Silent), "");
+        assert_eq!(
+            trace(TraceMode::Registers),
+            " cx:0x0->0x3\n cx:0x3->0x5 flags:->P\n\n"
+        );
+        assert_eq!(
+            trace(TraceMode::Full),
+            "mov cx, 3 ; Clocks: +4 = 4 | ip:0x0->0x3 cx:0x0->0x3\n\
+             add cx, 2 ; Clocks: +4 = 8 | ip:0x3->0x6 cx:0x3->0x5 flags:->P\n\
+             hlt ; Clocks: +2 = 10 | ip:0x6->0x7\n"
+        );
+    }
+
+    #[test]
+    fn test_debug_steps() {
+        // mov cx, 3; add cx, 2; mov dx, cx; hlt
+        let mut state = State::new(&[0xb9, 0x03, 0x00, 0x83, 0xc1, 0x02, 0x89, 0xca]);
+
+        debug(&mut state, "step\nregs\nbogus\nmem 0 8\n".as_bytes());
+        assert_eq!(state.get_value(Reg(Register::CX)), 3);
+        assert_eq!(state.iptr, 3);
+
+        debug(&mut state, "\nstep 1\nquit\nstep\n".as_bytes());
+        assert_eq!(state.get_value(Reg(Register::CX)), 5);
+        assert_eq!(state.get_value(Reg(Register::DX)), 5);
+        assert_eq!(state.iptr, 8);
+
+        debug(&mut state, "continue\n".as_bytes());
+        assert_eq!(state.iptr, 9);
+    }
+
+    #[test]
+    fn test_parse_debug_command() {
+        assert_eq!("".parse(), Ok(DebugCommand::Step(1)));
+        assert_eq!("s 10".parse(), Ok(DebugCommand::Step(10)));
+        assert_eq!("continue".parse(), Ok(DebugCommand::Continue));
+        assert_eq!("mem 0x100".parse(), Ok(DebugCommand::Mem { addr: 0x100, len: 16 }));
+        assert_eq!("m 16 4".parse(), Ok(DebugCommand::Mem { addr: 16, len: 4 }));
+        assert!("mem".parse::<DebugCommand>().is_err());
+        assert!("jump 4".parse::<DebugCommand>().is_err());
+    }
+
+    #[test]
+    fn test_radix() {
+        // mov cx, -800; add cx, 800; hlt
+        let binary = [0xb9, 0xe0, 0xfc, 0x81, 0xc1, 0x20, 0x03];
+
+        let trace = |radix| {
+            let mut state = State::new(&binary);
+            state.radix = radix;
+            state.trace_mode = TraceMode::Full;
+            state.capture_trace();
+
+            while state.step().is_some() {}
+            state.take_trace()
+        };
 
-        assert_eq!(state.cycles_estimate, 291);
+        assert_eq!(
+            trace(Radix::Hex),
+            "mov cx, -800 ; Clocks: +4 = 4 | ip:0x0->0x3 cx:0x0->0xfce0\n\
+             add cx, 800 ; Clocks: +4 = 8 | ip:0x3->0x7 cx:0xfce0->0x0 flags:->PZ\n\
+             hlt ; Clocks: +2 = 10 | ip:0x7->0x8\n"
+        );
+        assert_eq!(
+            trace(Radix::Decimal),
+            "mov cx, -800 ; Clocks: +4 = 4 | ip:0->3 cx:0->64736\n\
+             add cx, 800 ; Clocks: +4 = 8 | ip:3->7 cx:64736->0 flags:->PZ\n\
+             hlt ; Clocks: +2 = 10 | ip:7->8\n"
+        );
     }
 }
@@ -0,0 +1,227 @@
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use exec::{exec, State};
+use parse::{decode_with_offsets, disassemble, Inst, InstStream};
+
+pub mod exec;
+pub mod parse;
+
+/// Disassembles a raw 8086 binary into asm that NASM can reassemble
+pub fn disassemble_bytes(binary: &[u8]) -> String {
+    disassemble(InstStream::from_binary(binary.to_vec()))
+}
+
+/// Runs a raw 8086 binary until it halts, without tracing anything
+pub fn simulate(binary: &[u8]) -> State {
+    exec(binary.to_vec())
+}
+
+pub fn assemble(input: &str) -> Vec<u8> {
+    let mut tmp_in = tempfile::NamedTempFile::new().unwrap();
+    let mut tmp_out = tempfile::NamedTempFile::new().unwrap();
+
+    tmp_in
+        .write_all(input.as_bytes())
+        .expect("Failed to write to asm file");
+    let output = Command::new("nasm")
+        .arg(tmp_in.path())
+        .arg("-o")
+        .arg(tmp_out.path())
+        .output()
+        .expect("Failed to run nasm, is it installed?");
+    assert!(
+        output.status.success(),
+        "NASM failed to assemble:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let mut buf = Vec::new();
+    tmp_out
+        .read_to_end(&mut buf)
+        .expect("Failed to read assembled file");
+
+    buf
+}
+
+pub fn nasm_available() -> bool {
+    Command::new("nasm")
+        .arg("-v")
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+/// The pre-assembled binary checked in next to a listing, e.g. `inputs/foo.bin` for
+/// `inputs/foo.asm`
+pub fn fixture_path(asm_path: &str) -> PathBuf {
+    Path::new(asm_path).with_extension("bin")
+}
+
+/// Assembles with NASM when it's installed, falling back to the pre-assembled fixture otherwise
+pub fn assemble_or_fixture(asm: &str, fixture_path: impl AsRef<Path>) -> Vec<u8> {
+    if nasm_available() {
+        return assemble(asm);
+    }
+
+    let fixture_path = fixture_path.as_ref();
+    std::fs::read(fixture_path).unwrap_or_else(|err| {
+        panic!(
+            "NASM is not installed and the fixture {} couldn't be read ({err})",
+            fixture_path.display()
+        )
+    })
+}
+
+pub fn test_unformatted(test_asm: &str) -> Vec<Inst> {
+    println!("TEST ASM:\n\n{test_asm}");
+    let expected = assemble(&test_asm);
+    let stream = InstStream::from_binary(expected.clone());
+    let generated = disassemble(stream.clone());
+
+    println!("GENERATED ASM:\n\n{generated}");
+    let actual = assemble(&generated);
+
+    assert_eq!(expected, actual);
+    stream
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|err| panic!("Test asm should fully decode, but hit an {err}"))
+}
+
+/// Round-trips `test_asm` through NASM, skipping with a message when NASM isn't installed
+pub fn test_against_string(test_asm: &str) {
+    if !nasm_available() {
+        println!("NASM is not installed, skipping the round trip of:\n{test_asm}");
+        return;
+    }
+
+    let input = format!("bits 16\n\n{test_asm}");
+    test_unformatted(&input);
+}
+
+pub fn test_against_file(path: &str) {
+    let test_asm = std::fs::read_to_string(path).expect("Failed to read test file");
+    let binary = assemble_or_fixture(&test_asm, fixture_path(path));
+    assert_matches_golden(&disassemble_bytes(&binary), golden_path(path));
+
+    if nasm_available() {
+        test_unformatted(&test_asm);
+    } else {
+        test_against_fixture(&test_asm, &binary);
+    }
+}
+
+/// The expected disassembly checked in next to a listing, e.g. `inputs/foo.txt` for
+/// `inputs/foo.asm`
+pub fn golden_path(asm_path: &str) -> PathBuf {
+    Path::new(asm_path).with_extension("txt")
+}
+
+/// Set to rewrite the golden files from the current disassembly rather than checking against them
+pub const UPDATE_GOLDENS_VAR: &str = "SIM8086_UPDATE_GOLDENS";
+
+/// Checks `generated` against the golden file line by line, listing every line that differs
+pub fn assert_matches_golden(generated: &str, golden_path: impl AsRef<Path>) {
+    let golden_path = golden_path.as_ref();
+    if std::env::var_os(UPDATE_GOLDENS_VAR).is_some() {
+        std::fs::write(golden_path, generated).expect("Failed to write golden file");
+        return;
+    }
+
+    let golden = std::fs::read_to_string(golden_path).unwrap_or_else(|err| {
+        panic!(
+            "Couldn't read golden file {} ({err}), rerun with {UPDATE_GOLDENS_VAR}=1 to create it",
+            golden_path.display()
+        )
+    });
+
+    let expected: Vec<_> = golden.lines().collect();
+    let actual: Vec<_> = generated.lines().collect();
+
+    let diff = line_diff(&expected, &actual);
+    assert!(
+        diff.is_empty(),
+        "Disassembly doesn't match {}:\n{diff}",
+        golden_path.display()
+    );
+}
+
+/// Disassembles the listing and checks its text against `expected_txt_path`, ignoring comments,
+/// blank lines and spacing so only the instructions themselves have to match
+pub fn test_text_against_file(asm_path: &str, expected_txt_path: impl AsRef<Path>) {
+    let expected_txt_path = expected_txt_path.as_ref();
+
+    let test_asm = std::fs::read_to_string(asm_path).expect("Failed to read test file");
+    let binary = assemble_or_fixture(&test_asm, fixture_path(asm_path));
+    let expected_txt = std::fs::read_to_string(expected_txt_path).unwrap_or_else(|err| {
+        panic!("Couldn't read expected text {} ({err})", expected_txt_path.display())
+    });
+
+    let expected = normalize_asm(&expected_txt);
+    let actual = normalize_asm(&disassemble_bytes(&binary));
+
+    let diff = line_diff(&expected, &actual);
+    assert!(
+        diff.is_empty(),
+        "Disassembly of {asm_path} doesn't match {}:\n{diff}",
+        expected_txt_path.display()
+    );
+}
+
+fn normalize_asm(asm: &str) -> Vec<String> {
+    asm.lines()
+        .map(|line| line.split(';').next().unwrap_or_default())
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Every line that differs, numbered from 1, as `- expected` then `+ actual`
+fn line_diff(expected: &[impl AsRef<str>], actual: &[impl AsRef<str>]) -> String {
+    let mut diff = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        let expected = expected.get(i).map(|line| line.as_ref());
+        let actual = actual.get(i).map(|line| line.as_ref());
+
+        if expected != actual {
+            diff += &format!("line {}:\n", i + 1);
+            diff += &format!("  - {}\n", expected.unwrap_or("<missing>"));
+            diff += &format!("  + {}\n", actual.unwrap_or("<missing>"));
+        }
+    }
+
+    diff
+}
+
+/// Checks memory from `start` against `expected`, reporting the first differing address
+pub fn assert_memory_eq(state: &State, start: usize, expected: &[u8]) {
+    let actual = state.dump_region(start, expected.len());
+
+    if let Some(i) = (0..expected.len()).find(|&i| actual.get(i) != Some(&expected[i])) {
+        panic!(
+            "Memory differs at {}: expected {:02x?}, got {:02x?}",
+            start + i,
+            &expected[i..],
+            &actual[i.min(actual.len())..]
+        );
+    }
+}
+
+/// Without an assembler the generated asm can't be round-tripped, so instead check that the
+/// fixture decodes completely, into one instruction per instruction line of the listing
+fn test_against_fixture(test_asm: &str, expected: &[u8]) {
+    let decoded = decode_with_offsets(expected);
+    let decoded_len = decoded.last().map_or(0, |(offset, n, _)| offset + n);
+
+    println!("GENERATED ASM:\n\n{}", disassemble(decoded.iter().map(|(_, _, inst)| Ok(inst.clone()))));
+    assert_eq!(decoded_len, expected.len(), "Fixture has undecodable bytes");
+
+    let listed = test_asm
+        .lines()
+        .map(|line| line.split(';').next().unwrap().trim())
+        .filter(|line| !line.is_empty() && *line != "bits 16" && !line.ends_with(':'))
+        .count();
+    assert_eq!(decoded.len(), listed);
+}
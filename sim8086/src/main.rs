@@ -1,87 +1,119 @@
 use std::{
     fs::File,
     io::{self, stdin, stdout, Read, Write},
-    process::Command,
 };
 
-use exec::exec;
-use parse::{disassemble, Inst, InstStream};
-
-pub mod exec;
-pub mod parse;
-
-pub fn assemble(input: &str) -> Vec<u8> {
-    let mut tmp_in = tempfile::NamedTempFile::new().unwrap();
-    let mut tmp_out = tempfile::NamedTempFile::new().unwrap();
+use sim8086::{
+    assemble, disassemble_bytes,
+    exec::{debug, run_all_homework, Radix, State, TraceMode},
+};
 
-    tmp_in
-        .write_all(input.as_bytes())
-        .expect("Failed to write to asm file");
-    Command::new("nasm")
-        .arg(tmp_in.path())
-        .arg("-o")
-        .arg(tmp_out.path())
-        .output()
-        .expect("NASM failed to assemble");
+fn print_homework_report() {
+    let results = run_all_homework();
 
-    let mut buf = Vec::new();
-    tmp_out
-        .read_to_end(&mut buf)
-        .expect("Failed to read assembled file");
+    println!("{:<45} RESULT", "LISTING");
+    for (listing, passed) in &results {
+        println!("{listing:<45} {}", if *passed { "PASS" } else { "FAIL" });
+    }
 
-    buf
+    let passed = results.iter().filter(|(_, passed)| *passed).count();
+    println!("\n{passed}/{} listings passed", results.len());
 }
 
-pub fn test_unformatted(test_asm: &str) -> Vec<Inst> {
-    println!("TEST ASM:\n\n{test_asm}");
-    let expected = assemble(&test_asm);
-    let stream = InstStream::from_binary(expected.clone());
-    let generated = disassemble(stream.clone());
-
-    println!("GENERATED ASM:\n\n{generated}");
-    let actual = assemble(&generated);
+/// Where the program comes from
+#[derive(Debug, Default)]
+enum Input {
+    /// Asm source piped in on stdin
+    #[default]
+    Stdin,
+    /// Asm source in a file, from `--asm <file>`
+    Asm(String),
+    /// An already-assembled binary, from `--disasm <file>`
+    Binary(String),
+}
 
-    assert_eq!(expected, actual);
-    stream.collect()
+impl Input {
+    fn load(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Input::Stdin => {
+                let mut asm = String::new();
+                stdin().read_to_string(&mut asm)?;
+                Ok(assemble(&asm))
+            }
+            Input::Asm(path) => Ok(assemble(&std::fs::read_to_string(path)?)),
+            Input::Binary(path) => std::fs::read(path),
+        }
+    }
 }
 
-pub fn test_against_string(test_asm: &str) {
-    let input = format!("bits 16\n\n{test_asm}");
-    test_unformatted(&input);
+#[derive(Debug, Default)]
+struct Options {
+    input: Input,
+    execute: bool,
+    dump: bool,
+    homework: bool,
+    radix: Radix,
+    debug_path: Option<String>,
 }
 
-pub fn test_against_file(path: &str) {
-    let test_asm = std::fs::read_to_string(path).expect("Failed to read test file");
-    test_unformatted(&test_asm);
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Self {
+        let mut options = Self::default();
+        let path_for = |flag: &str, args: &mut dyn Iterator<Item = String>| {
+            args.next()
+                .unwrap_or_else(|| panic!("Expected a path after {flag}"))
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                // Commands come from stdin, so the program has to come from a file
+                "--debug" => options.debug_path = Some(path_for("--debug", &mut args)),
+                "--asm" => options.input = Input::Asm(path_for("--asm", &mut args)),
+                "--disasm" => options.input = Input::Binary(path_for("--disasm", &mut args)),
+                "--exec" => options.execute = true,
+                "--dump" => options.dump = true,
+                "--homework" => options.homework = true,
+                "--decimal" => options.radix = Radix::Decimal,
+                _ => (),
+            }
+        }
+
+        options
+    }
 }
 
 fn main() -> io::Result<()> {
-    let mut asm = String::new();
-    stdin().read_to_string(&mut asm)?;
+    let options = Options::parse(std::env::args().skip(1));
 
-    let binary = assemble(&asm);
+    if options.homework {
+        print_homework_report();
+        return Ok(());
+    }
 
-    let mut execute = false;
-    let mut dump = false;
+    if let Some(path) = options.debug_path {
+        let binary = assemble(&std::fs::read_to_string(path)?);
 
-    for arg in std::env::args().skip(1) {
-        match arg.as_str() {
-            "--exec" => execute = true,
-            "--dump" => dump = true,
-            _ => (),
-        }
+        let mut state = State::new(&binary);
+        state.radix = options.radix;
+        state.trace_mode = TraceMode::Full;
+
+        debug(&mut state, stdin().lock());
+        return Ok(());
     }
 
-    if !execute {
-        let stream: Vec<_> = InstStream::from_binary(binary).collect();
-        let disas = disassemble(stream.into_iter());
+    let binary = options.input.load()?;
 
-        return stdout().write_all(disas.as_bytes());
+    if !options.execute {
+        return stdout().write_all(disassemble_bytes(&binary).as_bytes());
     };
 
-    let state = exec(binary);
+    let mut state = State::new(&binary);
+    state.radix = options.radix;
+    state.trace_mode = TraceMode::Full;
+
+    while state.step().is_some() {}
 
-    if dump {
+    if options.dump {
         let mut outfile = File::create("dump.data")?;
         outfile.write_all(&state.memory)?;
     }
@@ -21,6 +21,11 @@ pub enum Register {
     BP,
     SI,
     DI,
+
+    ES,
+    CS,
+    SS,
+    DS,
 }
 
 impl Register {
@@ -37,6 +42,21 @@ impl Register {
         }
     }
 
+    /// Whether this is a 16-bit register
+    pub fn is_wide(&self) -> bool {
+        !matches!(
+            self,
+            Register::AL
+                | Register::CL
+                | Register::DL
+                | Register::BL
+                | Register::AH
+                | Register::CH
+                | Register::DH
+                | Register::BH
+        )
+    }
+
     fn to_string(&self) -> String {
         match self {
             Register::AL => "al",
@@ -55,6 +75,10 @@ impl Register {
             Register::BP => "bp",
             Register::SI => "si",
             Register::DI => "di",
+            Register::ES => "es",
+            Register::CS => "cs",
+            Register::SS => "ss",
+            Register::DS => "ds",
         }
         .to_string()
     }
@@ -71,6 +95,8 @@ pub struct EffAddr {
     pub base: Option<Register>,
     pub index: Option<Register>,
     pub offset: Option<i16>,
+    /// Set by a segment override prefix, otherwise the default segment is used
+    pub segment: Option<Register>,
 }
 
 impl EffAddr {
@@ -82,41 +108,49 @@ impl EffAddr {
                 base: Some(BX),
                 index: Some(SI),
                 offset: None,
+                segment: None,
             },
             EffAddr {
                 base: Some(BX),
                 index: Some(DI),
                 offset: None,
+                segment: None,
             },
             EffAddr {
                 base: Some(BP),
                 index: Some(SI),
                 offset: None,
+                segment: None,
             },
             EffAddr {
                 base: Some(BP),
                 index: Some(DI),
                 offset: None,
+                segment: None,
             },
             EffAddr {
                 base: Some(SI),
                 index: None,
                 offset: None,
+                segment: None,
             },
             EffAddr {
                 base: Some(DI),
                 index: None,
                 offset: None,
+                segment: None,
             },
             EffAddr {
                 base: Some(BP),
                 index: None,
                 offset: None,
+                segment: None,
             },
             EffAddr {
                 base: Some(BX),
                 index: None,
                 offset: None,
+                segment: None,
             },
         ];
 
@@ -131,6 +165,7 @@ impl EffAddr {
                             base: None,
                             index: None,
                             offset: Some(disp),
+                            segment: None,
                         },
                     )
                 } else {
@@ -160,6 +195,10 @@ impl EffAddr {
 
 impl Display for EffAddr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(segment) = self.segment {
+            write!(f, "{segment}:")?;
+        }
+
         write!(f, "[")?;
         if let Some(base) = self.base {
             write!(f, "{base}")?;
@@ -194,11 +233,13 @@ const fn get_bits(byte: u8, offset: u8, len: u8) -> u8 {
     (byte << offset) >> (8 - len)
 }
 
+/// A sign-extended immediate is a single byte in the encoding, but stands for the 16-bit value
+/// it extends to when the operation is wide
 fn get_data(sign_extend: bool, wide: bool, data_bytes: &[u8]) -> (usize, u16) {
-    if !sign_extend && wide {
-        (2, u16::from_le_bytes([data_bytes[0], data_bytes[1]]))
-    } else {
-        (1, data_bytes[0] as u16)
+    match (sign_extend, wide) {
+        (false, true) => (2, u16::from_le_bytes([data_bytes[0], data_bytes[1]])),
+        (true, true) => (1, data_bytes[0] as i8 as u16),
+        (_, false) => (1, data_bytes[0] as u16),
     }
 }
 
@@ -210,21 +251,26 @@ fn get_disp(wide: bool, data_bytes: &[u8]) -> (usize, i16) {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum ArithOps {
     ADD,
+    OR,
+    ADC,
+    SBB,
+    AND,
     SUB,
+    XOR,
     CMP,
 }
 
 impl ArithOps {
-    fn from_opcode(byte: u8) -> Option<Self> {
-        match byte {
-            0b000 => Some(Self::ADD),
-            0b101 => Some(Self::SUB),
-            0b111 => Some(Self::CMP),
-            _ => None,
-        }
+    /// Every arithmetic/logical encoding family uses the same 3-bit field to pick the operation
+    fn from_opcode(byte: u8) -> Self {
+        use ArithOps::*;
+
+        const OPS: [ArithOps; 8] = [ADD, OR, ADC, SBB, AND, SUB, XOR, CMP];
+
+        OPS[byte as usize]
     }
 }
 
@@ -236,6 +282,7 @@ pub enum Operand {
     MemByte(EffAddr),
     MemWord(EffAddr),
     RelOffsetByte(i8),
+    RelOffsetWord(i16),
 }
 
 impl Operand {
@@ -243,8 +290,9 @@ impl Operand {
         Self::Reg(Register::from_encoding(reg, wide))
     }
 
+    /// The operand's width only follows the w bit. The s bit widens the immediate that comes
+    /// after, not the destination, so `0x82` still writes a byte.
     fn from_rm_encoding(
-        sign_extend: bool,
         wide: bool,
         mode: u8,
         rm: u8,
@@ -257,7 +305,7 @@ impl Operand {
             let (disp_size, expr) = EffAddr::from_encoding(rm, mode, disp_bytes);
             (
                 disp_size,
-                if sign_extend || wide {
+                if wide {
                     Self::MemWord(expr)
                 } else {
                     Self::MemByte(expr)
@@ -270,23 +318,30 @@ impl Operand {
         let (n, data) = get_data(sign_extend, wide, data_bytes);
         (
             n,
-            if n == 1 {
-                Self::ImmByte(data as u8)
-            } else {
+            if wide {
                 Self::ImmWord(data)
+            } else {
+                Self::ImmByte(data as u8)
             },
         )
     }
 }
 
+/// Immediates only hold their bits, so the instruction decides how they read. The alternate flag
+/// (`{:#}`) prints them as signed, e.g. `mov cx, -3922` rather than `61614`.
+///
+/// The size keyword is left to the instruction too, as it's only needed when no operand is a
+/// register
 impl Display for Operand {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Operand::Reg(x) => write!(f, "{x}"),
-            Operand::ImmByte(x) => write!(f, "byte {x}"),
-            Operand::ImmWord(x) => write!(f, "word {x}"),
-            Operand::MemByte(x) => write!(f, "byte {x}"),
-            Operand::MemWord(x) => write!(f, "word {x}"),
+            Operand::ImmByte(x) if f.alternate() => write!(f, "{}", *x as i8),
+            Operand::ImmWord(x) if f.alternate() => write!(f, "{}", *x as i16),
+            Operand::ImmByte(x) => write!(f, "{x}"),
+            Operand::ImmWord(x) => write!(f, "{x}"),
+            Operand::MemByte(x) => write!(f, "{x}"),
+            Operand::MemWord(x) => write!(f, "{x}"),
             Operand::RelOffsetByte(x) => {
                 let offset = x + 2;
                 if offset > 0 {
@@ -297,15 +352,70 @@ impl Display for Operand {
                     write!(f, "${offset}+0")
                 }
             }
+            Operand::RelOffsetWord(x) => {
+                let offset = *x as i32 + 3;
+                if offset > 0 {
+                    write!(f, "$+{offset}+0")
+                } else if offset == 0 {
+                    write!(f, "$+0")
+                } else {
+                    write!(f, "${offset}+0")
+                }
+            }
         }
     }
 }
 
+/// Prefixes that modify the whole instruction after them, rather than an operand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prefix {
+    Lock,
+    /// Also known as REPE/REPZ when used with CMPS and SCAS
+    Rep,
+    Repne,
+    /// A segment override with no memory operand to attach to, e.g. `ss mov ax, bx`. It does
+    /// nothing, but keeping it means the bytes reassemble the same.
+    Segment(Register),
+}
+
+impl Display for Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let as_str = match self {
+            Prefix::Lock => "lock",
+            Prefix::Rep => "rep",
+            Prefix::Repne => "repne",
+            Prefix::Segment(segment) => return write!(f, "{segment}"),
+        };
+
+        write!(f, "{as_str}")
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Inst {
     MOV(Operand, Operand),
     ADD(Operand, Operand),
+    OR(Operand, Operand),
+    ADC(Operand, Operand),
+    SBB(Operand, Operand),
+    AND(Operand, Operand),
     SUB(Operand, Operand),
+    XOR(Operand, Operand),
     CMP(Operand, Operand),
+    TEST(Operand, Operand),
+    /// Loads the address of the memory operand, rather than what's stored there
+    LEA(Operand, Operand),
+    INC(Operand),
+    DEC(Operand),
+    PUSH(Operand),
+    POP(Operand),
+    PUSHF,
+    POPF,
+    MOVS { wide: bool },
+    CMPS { wide: bool },
+    SCAS { wide: bool },
+    LODS { wide: bool },
+    STOS { wide: bool },
     JO(Operand),
     JNO(Operand),
     JB(Operand),
@@ -326,18 +436,75 @@ pub enum Inst {
     LOOPZ(Operand),
     LOOP(Operand),
     JCXZ(Operand),
+    /// Direct intersegment jump, to `segment:offset`
+    JMPF { segment: u16, offset: u16 },
+    /// Direct intersegment call, to `segment:offset`
+    CALLF { segment: u16, offset: u16 },
+    /// Direct call within the segment, relative to the next instruction
+    CALL(Operand),
+    /// Return within the segment, releasing that many bytes of arguments from the stack if given
+    RET(Option<u16>),
     HLT,
+    Prefixed(Prefix, Box<Inst>),
 }
 
+/// Longest instruction without prefixes, e.g. an opcode, mod/rm byte, 16-bit displacement and
+/// 16-bit immediate
+const MAX_INST_LEN: usize = 6;
+
 impl Inst {
+    /// Returns None for bytes that don't start a known instruction, or that stop before the
+    /// instruction does
     pub fn from_encoding(binary: &[u8]) -> Option<(usize, Self)> {
+        if binary.is_empty() {
+            return None;
+        }
+
+        if binary.len() >= MAX_INST_LEN {
+            return Self::decode(binary);
+        }
+
+        // Decoding reads as far as the form needs, so a short buffer is decoded against trailing
+        // zeroes, then rejected if the instruction runs into them
+        let mut padded = [0; MAX_INST_LEN];
+        padded[..binary.len()].copy_from_slice(binary);
+
+        Self::decode(&padded).filter(|(n, _)| *n <= binary.len())
+    }
+
+    fn decode(binary: &[u8]) -> Option<(usize, Self)> {
         let byte = binary[0];
-        if byte == 0b11110100 {
+        if byte & 0b11100111 == 0b00100110 {
+            // Segment override prefix, applying to the memory operand of the next instruction
+            const SEGMENTS: [Register; 4] = [Register::ES, Register::CS, Register::SS, Register::DS];
+
+            let segment = SEGMENTS[get_bits(byte, 3, 2) as usize];
+            let (n, inst) = Self::from_encoding(binary.get(1..).filter(|rest| !rest.is_empty())?)?;
+            Some((1 + n, inst.with_segment(segment)))
+        } else if matches!(byte, 0xf0 | 0xf2 | 0xf3) {
+            let prefix = match byte {
+                0xf0 => Prefix::Lock,
+                0xf2 => Prefix::Repne,
+                _ => Prefix::Rep,
+            };
+
+            let (n, inst) = Self::from_encoding(binary.get(1..).filter(|rest| !rest.is_empty())?)?;
+            Some((1 + n, Self::Prefixed(prefix, Box::new(inst))))
+        } else if byte == 0b11110100 {
             Some((1, Self::HLT))
         } else if get_bits(byte, 0, 6) == 0b100010 {
             // Some(Self::MovRmToFromReg)
             let (n, op1, op2) = mod_reg_rm(binary)?;
             Some((n, Self::MOV(op1, op2)))
+        } else if byte == 0x8d {
+            // Some(Self::LEA), which only makes sense with a memory operand. The d bit is clear,
+            // but the register is always the destination.
+            let (n, addr, reg) = mod_reg_rm(binary)?;
+            if let Operand::Reg(_) = addr {
+                return None;
+            }
+
+            Some((n, Self::LEA(reg, addr)))
         } else if get_bits(byte, 0, 4) == 0b1011 {
             // Some(Self::MovImmToReg)
             let wide = get_bit(byte, 4);
@@ -362,27 +529,111 @@ impl Inst {
         } else if get_bits(byte, 0, 2) == 0b00 && !get_bit(byte, 5) {
             // Some(Self::ArithToFromReg)
 
-            let arith = ArithOps::from_opcode(get_bits(binary[0], 2, 3))
-                .expect("Expected arithmetic operation to have a valid arithmetic octal");
+            let arith = ArithOps::from_opcode(get_bits(binary[0], 2, 3));
 
             let (n, op1, op2) = mod_reg_rm(binary)?;
             Some((n, Self::new_arithmetic(arith, op1, op2)))
         } else if get_bits(byte, 0, 6) == 0b100000 {
             // Some(Self::ArithImmToRm)
 
-            let arith = ArithOps::from_opcode(get_bits(binary[1], 2, 3))
-                .expect("Expected arithmetic operation to have a valid arithmetic octal");
+            let arith = ArithOps::from_opcode(get_bits(binary[1], 2, 3));
 
             let (n, op1, op2) = imm_to_rm(true, binary)?;
             Some((n, Self::new_arithmetic(arith, op1, op2)))
         } else if get_bits(byte, 0, 2) == 0b00 && get_bits(byte, 5, 2) == 0b10 {
             // Some(Self::ArithWithAcc)
 
-            let arith = ArithOps::from_opcode(get_bits(binary[0], 2, 3))
-                .expect("Expected arithmetic operation to have a valid arithmetic octal");
+            let arith = ArithOps::from_opcode(get_bits(binary[0], 2, 3));
 
             let (n, op1, op2) = const_with_acc(false, false, binary)?;
             Some((n, Self::new_arithmetic(arith, op1, op2)))
+        } else if get_bits(byte, 0, 7) == 0b1000010 {
+            // Some(Self::TestRmWithReg)
+            let (n, op1, op2) = mod_reg_rm(binary)?;
+            Some((n, Self::TEST(op1, op2)))
+        } else if get_bits(byte, 0, 7) == 0b1111011 && get_bits(binary[1], 2, 3) == 0b000 {
+            // Some(Self::TestImmWithRm), the rest of the F6/F7 group isn't decoded yet
+            let (n, op1, op2) = imm_to_rm(false, binary)?;
+            Some((n, Self::TEST(op1, op2)))
+        } else if get_bits(byte, 0, 7) == 0b1010100 {
+            // Some(Self::TestImmWithAcc)
+            let (n, op1, op2) = const_with_acc(false, false, binary)?;
+            Some((n, Self::TEST(op1, op2)))
+        } else if get_bits(byte, 0, 7) == 0b1111111 && get_bits(binary[1], 2, 2) == 0b00 {
+            // Some(Self::IncDecRm), the rest of the FE/FF group isn't decoded yet
+            let wide = get_bit(byte, 7);
+            let mode = get_bits(binary[1], 0, 2);
+            let rm = get_bits(binary[1], 5, 3);
+
+            let (disp_size, op) = Operand::from_rm_encoding(wide, mode, rm, &binary[2..]);
+            let inst = if get_bit(binary[1], 4) { Self::DEC(op) } else { Self::INC(op) };
+            Some((2 + disp_size, inst))
+        } else if byte == 0xff && get_bits(binary[1], 2, 3) == 0b110 {
+            // Some(Self::PushRm)
+            let mode = get_bits(binary[1], 0, 2);
+            let rm = get_bits(binary[1], 5, 3);
+
+            let (disp_size, op) = Operand::from_rm_encoding(true, mode, rm, &binary[2..]);
+            Some((2 + disp_size, Self::PUSH(op)))
+        } else if byte == 0x8f && get_bits(binary[1], 2, 3) == 0b000 {
+            // Some(Self::PopRm)
+            let mode = get_bits(binary[1], 0, 2);
+            let rm = get_bits(binary[1], 5, 3);
+
+            let (disp_size, op) = Operand::from_rm_encoding(true, mode, rm, &binary[2..]);
+            Some((2 + disp_size, Self::POP(op)))
+        } else if get_bits(byte, 0, 4) == 0b0101 {
+            // Some(Self::PushReg) || Some(Self::PopReg)
+            let op = Operand::from_reg_encoding(get_bits(byte, 5, 3), true);
+            Some((1, if get_bit(byte, 4) { Self::POP(op) } else { Self::PUSH(op) }))
+        } else if byte & 0b11100110 == 0b00000110 {
+            // Some(Self::PushSeg) || Some(Self::PopSeg)
+            const SEGMENTS: [Register; 4] = [Register::ES, Register::CS, Register::SS, Register::DS];
+
+            let op = Operand::Reg(SEGMENTS[get_bits(byte, 3, 2) as usize]);
+            Some((1, if get_bit(byte, 7) { Self::POP(op) } else { Self::PUSH(op) }))
+        } else if byte == 0x9c {
+            Some((1, Self::PUSHF))
+        } else if byte == 0x9d {
+            Some((1, Self::POPF))
+        } else if get_bits(byte, 0, 4) == 0b0100 {
+            // Some(Self::IncDecReg)
+            let op = Operand::from_reg_encoding(get_bits(byte, 5, 3), true);
+            Some((1, if get_bit(byte, 4) { Self::DEC(op) } else { Self::INC(op) }))
+        } else if matches!(byte, 0xa4..=0xa7 | 0xaa..=0xaf) {
+            // Some(Self::String)
+            let wide = get_bit(byte, 7);
+            let inst = match get_bits(byte, 4, 3) {
+                0b010 => Self::MOVS { wide },
+                0b011 => Self::CMPS { wide },
+                0b101 => Self::STOS { wide },
+                0b110 => Self::LODS { wide },
+                0b111 => Self::SCAS { wide },
+                _ => unreachable!("Not a string instruction"),
+            };
+            Some((1, inst))
+        } else if matches!(byte, 0xea | 0x9a) {
+            // Some(Self::JMPF) || Some(Self::CALLF), the offset comes before the segment
+            let ptr = binary.get(1..5)?;
+            let offset = u16::from_le_bytes([ptr[0], ptr[1]]);
+            let segment = u16::from_le_bytes([ptr[2], ptr[3]]);
+
+            let inst = if byte == 0xea {
+                Self::JMPF { segment, offset }
+            } else {
+                Self::CALLF { segment, offset }
+            };
+            Some((5, inst))
+        } else if byte == 0xe8 {
+            // Some(Self::CALL)
+            let disp = binary.get(1..3)?;
+            Some((3, Self::CALL(Operand::RelOffsetWord(i16::from_le_bytes([disp[0], disp[1]])))))
+        } else if byte == 0xc3 {
+            Some((1, Self::RET(None)))
+        } else if byte == 0xc2 {
+            // Some(Self::RetImm)
+            let data = binary.get(1..3)?;
+            Some((3, Self::RET(Some(u16::from_le_bytes([data[0], data[1]])))))
         } else if get_bits(byte, 0, 4) == 0b0111 {
             // Some(Self::JMP) || Some(Self::LOOP)
             Some(Self::new_jmp(binary))
@@ -393,10 +644,69 @@ impl Inst {
         }
     }
 
+    fn with_segment(self, segment: Register) -> Self {
+        let seg = |op: Operand| match op {
+            Operand::MemByte(ea) => Operand::MemByte(EffAddr { segment: Some(segment), ..ea }),
+            Operand::MemWord(ea) => Operand::MemWord(EffAddr { segment: Some(segment), ..ea }),
+            op => op,
+        };
+
+        match self {
+            Self::Prefixed(prefix, inst) => {
+                Self::Prefixed(prefix, Box::new(inst.with_segment(segment)))
+            }
+            inst if !inst.has_memory_operand() => {
+                Self::Prefixed(Prefix::Segment(segment), Box::new(inst))
+            }
+            Self::MOV(op1, op2) => Self::MOV(seg(op1), seg(op2)),
+            Self::ADD(op1, op2) => Self::ADD(seg(op1), seg(op2)),
+            Self::OR(op1, op2) => Self::OR(seg(op1), seg(op2)),
+            Self::ADC(op1, op2) => Self::ADC(seg(op1), seg(op2)),
+            Self::SBB(op1, op2) => Self::SBB(seg(op1), seg(op2)),
+            Self::AND(op1, op2) => Self::AND(seg(op1), seg(op2)),
+            Self::SUB(op1, op2) => Self::SUB(seg(op1), seg(op2)),
+            Self::XOR(op1, op2) => Self::XOR(seg(op1), seg(op2)),
+            Self::CMP(op1, op2) => Self::CMP(seg(op1), seg(op2)),
+            Self::TEST(op1, op2) => Self::TEST(seg(op1), seg(op2)),
+            Self::LEA(op1, op2) => Self::LEA(seg(op1), seg(op2)),
+            Self::INC(op) => Self::INC(seg(op)),
+            Self::DEC(op) => Self::DEC(seg(op)),
+            Self::PUSH(op) => Self::PUSH(seg(op)),
+            Self::POP(op) => Self::POP(seg(op)),
+            inst => inst,
+        }
+    }
+
+    fn has_memory_operand(&self) -> bool {
+        let is_mem = |op: &Operand| matches!(op, Operand::MemByte(_) | Operand::MemWord(_));
+
+        match self {
+            Self::MOV(op1, op2)
+            | Self::ADD(op1, op2)
+            | Self::OR(op1, op2)
+            | Self::ADC(op1, op2)
+            | Self::SBB(op1, op2)
+            | Self::AND(op1, op2)
+            | Self::SUB(op1, op2)
+            | Self::XOR(op1, op2)
+            | Self::CMP(op1, op2)
+            | Self::TEST(op1, op2)
+            | Self::LEA(op1, op2) => is_mem(op1) || is_mem(op2),
+            Self::INC(op) | Self::DEC(op) | Self::PUSH(op) | Self::POP(op) => is_mem(op),
+            Self::Prefixed(_, inst) => inst.has_memory_operand(),
+            _ => false,
+        }
+    }
+
     fn new_arithmetic(arith: ArithOps, op1: Operand, op2: Operand) -> Self {
         match arith {
             ArithOps::ADD => Self::ADD(op1, op2),
+            ArithOps::OR => Self::OR(op1, op2),
+            ArithOps::ADC => Self::ADC(op1, op2),
+            ArithOps::SBB => Self::SBB(op1, op2),
+            ArithOps::AND => Self::AND(op1, op2),
             ArithOps::SUB => Self::SUB(op1, op2),
+            ArithOps::XOR => Self::XOR(op1, op2),
             ArithOps::CMP => Self::CMP(op1, op2),
         }
     }
@@ -442,13 +752,82 @@ impl Inst {
     }
 }
 
+impl Operand {
+    /// Whether this operand is 16 bits, jumps count as wide when their offset is a word
+    pub fn is_wide(&self) -> bool {
+        match self {
+            Operand::Reg(reg) => reg.is_wide(),
+            Operand::ImmWord(_) | Operand::MemWord(_) | Operand::RelOffsetWord(_) => true,
+            Operand::ImmByte(_) | Operand::MemByte(_) | Operand::RelOffsetByte(_) => false,
+        }
+    }
+
+    fn size_keyword(&self) -> Option<&'static str> {
+        match self {
+            Operand::ImmByte(_) | Operand::MemByte(_) => Some("byte"),
+            Operand::ImmWord(_) | Operand::MemWord(_) => Some("word"),
+            Operand::Reg(_) | Operand::RelOffsetByte(_) | Operand::RelOffsetWord(_) => None,
+        }
+    }
+}
+
+/// Writes `mnemonic ops...`, sizing the first operand only when no register operand already fixes
+/// the size for NASM, e.g. `mov word [bx], 5` but `mov [bx], ax`
+fn write_sized(
+    f: &mut std::fmt::Formatter<'_>,
+    mnemonic: &str,
+    ops: &[&Operand],
+    signed: bool,
+) -> std::fmt::Result {
+    write!(f, "{mnemonic} ")?;
+
+    if !ops.iter().any(|op| matches!(op, Operand::Reg(_))) {
+        if let Some(size) = ops.first().and_then(|op| op.size_keyword()) {
+            write!(f, "{size} ")?;
+        }
+    }
+
+    for (i, op) in ops.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+
+        if signed {
+            write!(f, "{op:#}")?;
+        } else {
+            write!(f, "{op}")?;
+        }
+    }
+
+    Ok(())
+}
+
 impl Display for Inst {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Inst::MOV(op1, op2) => write!(f, "mov {op1}, {op2}"),
-            Inst::ADD(op1, op2) => write!(f, "add {op1}, {op2}"),
-            Inst::SUB(op1, op2) => write!(f, "sub {op1}, {op2}"),
-            Inst::CMP(op1, op2) => write!(f, "cmp {op1}, {op2}"),
+            // Arithmetic reads naturally with negative immediates, the logical ops take masks
+            Inst::MOV(op1, op2) => write_sized(f, "mov", &[op1, op2], true),
+            Inst::ADD(op1, op2) => write_sized(f, "add", &[op1, op2], true),
+            Inst::OR(op1, op2) => write_sized(f, "or", &[op1, op2], false),
+            Inst::ADC(op1, op2) => write_sized(f, "adc", &[op1, op2], true),
+            Inst::SBB(op1, op2) => write_sized(f, "sbb", &[op1, op2], true),
+            Inst::AND(op1, op2) => write_sized(f, "and", &[op1, op2], false),
+            Inst::SUB(op1, op2) => write_sized(f, "sub", &[op1, op2], true),
+            Inst::XOR(op1, op2) => write_sized(f, "xor", &[op1, op2], false),
+            Inst::CMP(op1, op2) => write_sized(f, "cmp", &[op1, op2], true),
+            Inst::TEST(op1, op2) => write_sized(f, "test", &[op1, op2], false),
+            Inst::LEA(op1, op2) => write_sized(f, "lea", &[op1, op2], false),
+            Inst::INC(op1) => write_sized(f, "inc", &[op1], false),
+            Inst::DEC(op1) => write_sized(f, "dec", &[op1], false),
+            Inst::PUSH(op1) => write_sized(f, "push", &[op1], false),
+            Inst::POP(op1) => write_sized(f, "pop", &[op1], false),
+            Inst::PUSHF => write!(f, "pushf"),
+            Inst::POPF => write!(f, "popf"),
+            Inst::MOVS { wide } => write!(f, "movs{}", if *wide { "w" } else { "b" }),
+            Inst::CMPS { wide } => write!(f, "cmps{}", if *wide { "w" } else { "b" }),
+            Inst::SCAS { wide } => write!(f, "scas{}", if *wide { "w" } else { "b" }),
+            Inst::LODS { wide } => write!(f, "lods{}", if *wide { "w" } else { "b" }),
+            Inst::STOS { wide } => write!(f, "stos{}", if *wide { "w" } else { "b" }),
             Inst::JO(op1) => write!(f, "jo {op1}"),
             Inst::JNO(op1) => write!(f, "jno {op1}"),
             Inst::JB(op1) => write!(f, "jb {op1}"),
@@ -469,7 +848,13 @@ impl Display for Inst {
             Inst::LOOPZ(op1) => write!(f, "loopz {op1}"),
             Inst::LOOP(op1) => write!(f, "loop {op1}"),
             Inst::JCXZ(op1) => write!(f, "jcxz {op1}"),
+            Inst::JMPF { segment, offset } => write!(f, "jmp {segment:#06x}:{offset:#06x}"),
+            Inst::CALLF { segment, offset } => write!(f, "call {segment:#06x}:{offset:#06x}"),
+            Inst::CALL(op1) => write!(f, "call {op1}"),
+            Inst::RET(None) => write!(f, "ret"),
+            Inst::RET(Some(pop)) => write!(f, "ret {pop}"),
             Inst::HLT => write!(f, "hlt"),
+            Inst::Prefixed(prefix, inst) => write!(f, "{prefix} {inst}"),
         }
     }
 }
@@ -486,7 +871,7 @@ fn mod_reg_rm(binary: &[u8]) -> Option<(usize, Operand, Operand)> {
     let rm = get_bits(b2, 5, 3);
 
     let mut r1 = Operand::from_reg_encoding(reg, wide);
-    let (disp_size, mut r2) = Operand::from_rm_encoding(false, wide, mode, rm, &binary[2..]);
+    let (disp_size, mut r2) = Operand::from_rm_encoding(wide, mode, rm, &binary[2..]);
 
     if !dest {
         let tmp = r1;
@@ -508,7 +893,7 @@ fn imm_to_rm(arith: bool, binary: &[u8]) -> Option<(usize, Operand, Operand)> {
     let mode = get_bits(b2, 0, 2);
     let rm = get_bits(b2, 5, 3);
 
-    let (disp_size, dest) = Operand::from_rm_encoding(sign_extend, wide, mode, rm, &binary[2..]);
+    let (disp_size, dest) = Operand::from_rm_encoding(wide, mode, rm, &binary[2..]);
 
     let (data_size, imm) = Operand::from_data_encoding(sign_extend, wide, &binary[2 + disp_size..]);
 
@@ -527,6 +912,7 @@ fn const_with_acc(flip: bool, is_mem: bool, binary: &[u8]) -> Option<(usize, Ope
             base: None,
             index: None,
             offset: Some(data as i16),
+            segment: None,
         };
 
         if wide {
@@ -549,9 +935,25 @@ fn const_with_acc(flip: bool, is_mem: bool, binary: &[u8]) -> Option<(usize, Ope
     }
 }
 
+/// A byte at `offset` that doesn't start any instruction the decoder knows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub byte: u8,
+    pub offset: usize,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown opcode {:#04x} at {:#06x}", self.byte, self.offset)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Undecodable bytes are kept as `db` so the output still reassembles to the original binary
 pub fn disassemble<I>(stream: I) -> String
 where
-    I: Iterator<Item = Inst>,
+    I: Iterator<Item = Result<Inst, DecodeError>>,
 {
     let mut disas = String::new();
 
@@ -559,13 +961,62 @@ where
     disas += "bits 16\n\n";
 
     for inst in stream {
-        disas += &inst.to_string();
+        match inst {
+            Ok(inst) => disas += &inst.to_string(),
+            Err(err) => disas += &format!("db {:#04x} ; {err}", err.byte),
+        }
+        disas += "\n";
+    }
+
+    disas
+}
+
+/// Like `disassemble`, with each instruction's bytes in a trailing comment, e.g. `mov cx, bx ; 89 d9`
+pub fn disassemble_annotated(binary: &[u8]) -> String {
+    let mut disas = String::new();
+
+    disas += "; This file was disassembled by Lawrence\n";
+    disas += "bits 16\n\n";
+
+    let mut stream = InstStream::from_binary(binary.to_vec());
+    let mut start = stream.iptr;
+    while let Some(inst) = stream.next() {
+        let bytes = binary[start..stream.iptr]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        match inst {
+            Ok(inst) => disas += &format!("{inst} ; {bytes}"),
+            Err(err) => disas += &format!("db {:#04x} ; {bytes}, {err}", err.byte),
+        }
         disas += "\n";
+
+        start = stream.iptr;
     }
 
     disas
 }
 
+/// Decodes every instruction along with its (byte offset, byte length), stopping at the first
+/// byte that can't be decoded
+pub fn decode_with_offsets(binary: &[u8]) -> Vec<(usize, usize, Inst)> {
+    let mut decoded = Vec::new();
+    let mut offset = 0;
+
+    while offset < binary.len() {
+        let Some((n, inst)) = Inst::from_encoding(&binary[offset..]) else {
+            break;
+        };
+
+        decoded.push((offset, n, inst));
+        offset += n;
+    }
+
+    decoded
+}
+
 #[derive(Debug, Clone)]
 pub struct InstStream {
     binary: Vec<u8>,
@@ -576,34 +1027,116 @@ impl InstStream {
     pub fn from_binary(binary: Vec<u8>) -> Self {
         Self { binary, iptr: 0 }
     }
+
+    /// Decodes the instruction at `offset`, along with its length, without moving `iptr`
+    pub fn peek_at(&self, offset: usize) -> Option<(usize, Inst)> {
+        Inst::from_encoding(self.binary.get(offset..)?)
+    }
 }
 
+/// Yields an error for each byte that can't be decoded, then carries on from the byte after it
 impl Iterator for InstStream {
-    type Item = Inst;
+    type Item = Result<Inst, DecodeError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while self.iptr < self.binary.len() {
-            let Some((n, parsed)) = Inst::from_encoding(&self.binary[self.iptr..]) else {
-                return None;
-            };
+        let rest = self.binary.get(self.iptr..).filter(|rest| !rest.is_empty())?;
 
-            self.iptr += n;
-            return Some(parsed);
+        match Inst::from_encoding(rest) {
+            Some((n, parsed)) => {
+                self.iptr += n;
+                Some(Ok(parsed))
+            }
+            None => {
+                let err = DecodeError { byte: rest[0], offset: self.iptr };
+                self.iptr += 1;
+                Some(Err(err))
+            }
         }
-
-        None
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{test_against_file, test_against_string};
+    use super::{
+        decode_with_offsets, disassemble, disassemble_annotated, DecodeError, Inst, InstStream,
+    };
+    use crate::{
+        assemble, nasm_available, test_against_file, test_against_string, test_text_against_file,
+    };
+
+    /// Checks that each encoding decodes on its own to exactly one instruction with the given text
+    fn assert_decodes(cases: &[(&[u8], &str)]) {
+        for (binary, expected) in cases {
+            let decoded = decode_with_offsets(binary);
+            assert_eq!(decoded.len(), 1, "{binary:02x?}");
+            assert_eq!(decoded[0].1, binary.len(), "{binary:02x?}");
+            assert_eq!(decoded[0].2.to_string(), *expected);
+        }
+    }
 
     #[test]
     fn mov_reg_to_reg() {
         test_against_string("mov cx, bx");
     }
 
+    #[test]
+    fn test_disassemble_annotated() {
+        let mut binary = std::fs::read("inputs/listing_0039_more_movs.bin").unwrap();
+        binary.push(0xd8);
+
+        let disas = disassemble_annotated(&binary);
+        assert!(disas.contains("\nmov si, bx ; 89 de\n"), "{disas}");
+        assert!(disas.ends_with("db 0xd8 ; d8, unknown opcode 0xd8 at 0x0029\n"), "{disas}");
+
+        let annotated = disas
+            .lines()
+            .filter_map(|line| line.split_once(" ; "))
+            .flat_map(|(_, comment)| comment.split(',').next().unwrap().split(' '))
+            .map(|byte| u8::from_str_radix(byte, 16).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(annotated, binary);
+    }
+
+    #[test]
+    fn test_text_hw1_hw2() {
+        // The course's own sources, whose headers, comments and blank lines only match once
+        // normalized away. The challenge listing puts its size keywords elsewhere, so it's left
+        // to its golden file.
+        for path in [
+            "inputs/listing_0037_single_register_mov.asm",
+            "inputs/listing_0038_many_register_mov.asm",
+            "inputs/listing_0039_more_movs.asm",
+        ] {
+            test_text_against_file(path, path);
+        }
+    }
+
+    #[test]
+    fn test_peek_at() {
+        let binary = std::fs::read("inputs/listing_0041_add_sub_cmp_jnz.bin").unwrap();
+        let decoded = decode_with_offsets(&binary);
+
+        let mut stream = InstStream::from_binary(binary.clone());
+        for _ in 0..3 {
+            stream.next();
+        }
+        let iptr = stream.iptr;
+
+        for (offset, n, inst) in &decoded {
+            let (peeked_n, peeked) = stream.peek_at(*offset).unwrap();
+            assert_eq!((peeked_n, peeked.to_string()), (*n, inst.to_string()));
+        }
+        assert_eq!(stream.iptr, iptr);
+
+        // Peeking mid-stream agrees with carrying on sequentially
+        let (n, peeked) = stream.peek_at(iptr).unwrap();
+        assert_eq!(stream.next().unwrap().unwrap().to_string(), peeked.to_string());
+        assert_eq!(stream.iptr, iptr + n);
+
+        assert!(stream.peek_at(binary.len()).is_none());
+        assert!(stream.peek_at(binary.len() + 10).is_none());
+    }
+
     #[test]
     fn test_hw1() {
         test_against_file("inputs/listing_0037_single_register_mov.asm");
@@ -662,6 +1195,22 @@ mod tests {
         test_against_string("mov [di + 901], word 347");
     }
 
+    #[test]
+    fn test_size_keywords() {
+        assert_decodes(&[
+            (&[0xc7, 0x07, 0x05, 0x00], "mov word [bx], 5"),
+            (&[0xc6, 0x03, 0x07], "mov byte [bp + di], 7"),
+            (&[0x89, 0x07], "mov [bx], ax"),
+            (&[0xb1, 0x0c], "mov cl, 12"),
+            (&[0xfe, 0x04], "inc byte [si]"),
+            // Registers already give the width, whatever the encoding
+            (&[0xb9, 0x05, 0x00], "mov cx, 5"),
+            (&[0xc7, 0xc1, 0x05, 0x00], "mov cx, 5"),
+            (&[0x83, 0xc1, 0x05], "add cx, 5"),
+            (&[0x89, 0x0f], "mov [bx], cx"),
+        ]);
+    }
+
     #[test]
     fn mov_direct_address() {
         test_against_string("mov bp, [5]");
@@ -697,8 +1246,302 @@ mod tests {
         test_against_string("cmp si, 2");
     }
 
+    #[test]
+    fn test_arith_with_acc() {
+        for op in ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"] {
+            test_against_string(&format!("{op} al, 5"));
+            test_against_string(&format!("{op} ax, 1000"));
+        }
+    }
+
+    #[test]
+    fn test_decode_arith_with_acc() {
+        let ops = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+
+        for (i, op) in ops.iter().enumerate() {
+            let opcode = (i as u8) << 3 | 0b100;
+
+            let decoded = decode_with_offsets(&[opcode, 0x05]);
+            assert_eq!(decoded.len(), 1);
+            assert_eq!(decoded[0].1, 2);
+            assert_eq!(decoded[0].2.to_string(), format!("{op} al, 5"));
+
+            let decoded = decode_with_offsets(&[opcode | 1, 0xe8, 0x03]);
+            assert_eq!(decoded.len(), 1);
+            assert_eq!(decoded[0].1, 3);
+            assert_eq!(decoded[0].2.to_string(), format!("{op} ax, 1000"));
+        }
+    }
+
+    #[test]
+    fn test_logical() {
+        test_against_string("and ax, bx\nor byte [bx], 7\nxor cx, cx\nand [bp + si + 4], dl\nor si, 1000");
+    }
+
+    #[test]
+    fn test_decode_logical() {
+        assert_decodes(&[
+            (&[0x21, 0xd8], "and ax, bx"),
+            (&[0x80, 0x0f, 0x07], "or byte [bx], 7"),
+            (&[0x31, 0xc9], "xor cx, cx"),
+            (&[0x83, 0xf6, 0xff], "xor si, 65535"),
+        ]);
+    }
+
+    #[test]
+    fn test_sign_extended_immediates() {
+        test_against_string(
+            "add ax, -1\ncmp ax, -128\nsub al, -1\nadd bx, -1\ncmp word [bp + 2], -128\ncmp al, -5\nsub ax, -300",
+        );
+    }
+
+    #[test]
+    fn test_decode_sign_extended_immediates() {
+        assert_decodes(&[
+            (&[0x05, 0xff, 0xff], "add ax, -1"),
+            (&[0x3d, 0x80, 0xff], "cmp ax, -128"),
+            (&[0x2c, 0xff], "sub al, -1"),
+            (&[0x3c, 0xfb], "cmp al, -5"),
+            (&[0x2d, 0xd4, 0xfe], "sub ax, -300"),
+            // The logical ops take masks, so they stay unsigned
+            (&[0x25, 0xff, 0xff], "and ax, 65535"),
+            (&[0x83, 0xc0, 0xff], "add ax, -1"),
+            (&[0x83, 0xf8, 0x80], "cmp ax, -128"),
+            (&[0x83, 0xe9, 0x7f], "sub cx, 127"),
+        ]);
+    }
+
+    #[test]
+    fn test_signed_immediates() {
+        // mov cx, -3922
+        let disas = disassemble(InstStream::from_binary(vec![0xb9, 0xae, 0xf0]));
+        assert!(disas.ends_with("mov cx, -3922\n"), "{disas}");
+
+        assert_decodes(&[
+            (&[0xb9, 0xae, 0xf0], "mov cx, -3922"),
+            (&[0xb2, 0xf4], "mov dl, -12"),
+            (&[0x25, 0x00, 0xff], "and ax, 65280"),
+            (&[0x80, 0xcb, 0x80], "or bl, 128"),
+        ]);
+
+        test_against_string("mov cx, -3922\nmov dl, -12\nand ax, 0xff00\nor bl, 0x80");
+    }
+
+    #[test]
+    fn test_far_jumps() {
+        let binary = vec![
+            0xea, 0x78, 0x56, 0x34, 0x12, // jmp 0x1234:0x5678
+            0x9a, 0x00, 0x01, 0x00, 0xf0, // call 0xf000:0x0100
+            0xf4, // hlt
+        ];
+
+        let decoded = decode_with_offsets(&binary);
+        let spans: Vec<_> = decoded.iter().map(|(offset, n, _)| (*offset, *n)).collect();
+        assert_eq!(spans, [(0, 5), (5, 5), (10, 1)]);
+
+        assert_eq!(decoded[0].2.to_string(), "jmp 0x1234:0x5678");
+        assert_eq!(decoded[1].2.to_string(), "call 0xf000:0x0100");
+
+        // Too short for the full pointer
+        assert!(Inst::from_encoding(&[0xea, 0x78, 0x56, 0x34]).is_none());
+
+        test_against_string("jmp 0x1234:0x5678\ncall 0xf000:0x0100");
+    }
+
+    #[test]
+    fn test_segment_override() {
+        test_against_string("mov ax, cs:[bx]\nmov es:[bp + di + 5], cl\nadd word ss:[1000], 7\nss mov ax, bx");
+    }
+
+    #[test]
+    fn test_decode_segment_override() {
+        assert_decodes(&[
+            (&[0x2e, 0x8b, 0x07], "mov ax, cs:[bx]"),
+            (&[0x26, 0x88, 0x4b, 0x05], "mov es:[bp + di + 5], cl"),
+            (&[0x3e, 0xa1, 0xe8, 0x03], "mov ax, ds:[1000]"),
+            (&[0x36, 0x89, 0xd8], "ss mov ax, bx"),
+            (&[0x26, 0xa4], "es movsb"),
+        ]);
+
+        // A prefix with nothing after it isn't an instruction
+        assert!(decode_with_offsets(&[0x2e]).is_empty());
+    }
+
+    #[test]
+    fn test_prefixes() {
+        test_against_string("rep stosb\nlock inc word [bx]\nrep movsw\nrepne scasb\nlock add [bx], ax\ndec cx\ninc al");
+    }
+
+    #[test]
+    fn test_decode_prefixes() {
+        assert_decodes(&[
+            (&[0xf3, 0xaa], "rep stosb"),
+            (&[0xf0, 0xff, 0x07], "lock inc word [bx]"),
+            (&[0xf3, 0xa5], "rep movsw"),
+            (&[0xf2, 0xae], "repne scasb"),
+            (&[0xf0, 0x01, 0x07], "lock add [bx], ax"),
+            (&[0xf0, 0x2e, 0xfe, 0x4f, 0x02], "lock dec byte cs:[bx + 2]"),
+            (&[0x49], "dec cx"),
+            (&[0xad], "lodsw"),
+        ]);
+
+        assert!(decode_with_offsets(&[0xf3]).is_empty());
+    }
+
+    #[test]
+    fn test_test() {
+        test_against_string("test al, 1\ntest bx, cx\ntest byte [bx + 2], 7\ntest ax, 1000");
+    }
+
+    #[test]
+    fn test_decode_test() {
+        assert_decodes(&[
+            (&[0xa8, 0x01], "test al, 1"),
+            (&[0x85, 0xcb], "test bx, cx"),
+            (&[0x84, 0x07], "test [bx], al"),
+            (&[0xf6, 0x47, 0x02, 0x07], "test byte [bx + 2], 7"),
+            (&[0xf7, 0xc1, 0xe8, 0x03], "test cx, 1000"),
+        ]);
+
+        // Only the TEST member of the F6 group is decoded
+        assert!(decode_with_offsets(&[0xf6, 0xd0]).is_empty());
+    }
+
+    #[test]
+    fn test_imm_to_rm_width() {
+        test_against_string(
+            "mov word [bx], 5\nmov byte [bx], 255\nadd word [bx], 5\nadd byte [bx], 5\nmov word [bx], 1000\nmov word [bx], -1",
+        );
+    }
+
+    #[test]
+    fn test_decode_imm_to_rm_width() {
+        assert_decodes(&[
+            (&[0xc7, 0x07, 0x05, 0x00], "mov word [bx], 5"),
+            (&[0xc6, 0x07, 0xff], "mov byte [bx], -1"),
+            (&[0xc7, 0x07, 0xe8, 0x03], "mov word [bx], 1000"),
+            (&[0xc7, 0x07, 0xff, 0xff], "mov word [bx], -1"),
+            (&[0x83, 0x07, 0x05], "add word [bx], 5"),
+            (&[0x80, 0x07, 0x05], "add byte [bx], 5"),
+            // The s bit is set, but without the w bit there's only a byte to write
+            (&[0x82, 0x07, 0x05], "add byte [bx], 5"),
+        ]);
+    }
+
+    #[test]
+    fn test_truncated_instructions() {
+        let cases: [&[u8]; 7] = [
+            &[],
+            &[0x88],
+            &[0x89, 0x87, 0xe8],
+            &[0xc7, 0x07, 0xe8],
+            &[0xb8, 0x01],
+            &[0x26, 0x8b],
+            &[0xf3, 0x2e, 0x89, 0x87],
+        ];
+
+        for binary in cases {
+            assert!(Inst::from_encoding(binary).is_none(), "{binary:02x?}");
+        }
+
+        // Exactly enough bytes still decodes, prefixes included
+        let (n, inst) = Inst::from_encoding(&[0x26, 0xc7, 0x87, 0xe8, 0x03, 0x05, 0x00]).unwrap();
+        assert_eq!(n, 7);
+        assert_eq!(inst.to_string(), "mov word es:[bx + 1000], 5");
+
+        let stream = InstStream::from_binary(vec![0x89, 0xd9, 0x88]).collect::<Vec<_>>();
+        assert!(matches!(stream[..], [Ok(Inst::MOV(..)), Err(DecodeError { byte: 0x88, offset: 2 })]));
+    }
+
+    #[test]
+    fn test_lea() {
+        test_against_string("lea bx, [bp + si + 4]\nlea ax, [1000]\nlea si, [bx - 2]");
+    }
+
+    #[test]
+    fn test_decode_lea() {
+        assert_decodes(&[
+            (&[0x8d, 0x5a, 0x04], "lea bx, [bp + si + 4]"),
+            (&[0x8d, 0x06, 0xe8, 0x03], "lea ax, [1000]"),
+            (&[0x8d, 0x77, 0xfe], "lea si, [bx - 2]"),
+        ]);
+
+        // There's no address to load from a register
+        assert!(decode_with_offsets(&[0x8d, 0xd8]).is_empty());
+    }
+
+    #[test]
+    fn test_push_pop() {
+        test_against_string("push ax\npush word [bp + 4]\npush es\npop ds\npop word [bx]\npop di\npushf\npopf");
+    }
+
+    #[test]
+    fn test_decode_push_pop() {
+        assert_decodes(&[
+            (&[0x50], "push ax"),
+            (&[0x5f], "pop di"),
+            (&[0xff, 0x76, 0x04], "push word [bp + 4]"),
+            (&[0x8f, 0x07], "pop word [bx]"),
+            (&[0x06], "push es"),
+            (&[0x1f], "pop ds"),
+            (&[0x9c], "pushf"),
+            (&[0x9d], "popf"),
+        ]);
+    }
+
+    #[test]
+    fn test_decode_call_ret() {
+        assert_decodes(&[
+            (&[0xe8, 0x05, 0x00], "call $+8+0"),
+            (&[0xe8, 0xf0, 0xff], "call $-13+0"),
+            (&[0xc3], "ret"),
+            (&[0xc2, 0x04, 0x00], "ret 4"),
+        ]);
+    }
+
     #[test]
     fn test_hw3() {
         test_against_file("inputs/listing_0041_add_sub_cmp_jnz.asm");
     }
+
+    #[test]
+    fn test_decode_with_offsets() {
+        let binary = [
+            0x89, 0xd9, // mov cx, bx
+            0xb9, 0x0c, 0x00, // mov cx, 12
+            0x83, 0x82, 0xe8, 0x03, 0x1d, // add word [bp + si + 1000], 29
+            0xf4, // hlt
+        ];
+
+        let decoded = decode_with_offsets(&binary);
+        let spans: Vec<_> = decoded.iter().map(|(offset, n, _)| (*offset, *n)).collect();
+
+        assert_eq!(spans, [(0, 2), (2, 3), (5, 5), (10, 1)]);
+        assert_eq!(decoded.iter().map(|(_, n, _)| n).sum::<usize>(), binary.len());
+        assert_eq!(decoded[2].2.to_string(), "add word [bp + si + 1000], 29");
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let binary = vec![
+            0x89, 0xd9, // mov cx, bx
+            0xf1, // undefined on the 8086
+            0xb9, 0x0c, 0x00, // mov cx, 12
+        ];
+
+        let errors: Vec<_> = InstStream::from_binary(binary.clone())
+            .filter_map(Result::err)
+            .collect();
+        assert_eq!(errors, [DecodeError { byte: 0xf1, offset: 2 }]);
+
+        let disas = disassemble(InstStream::from_binary(binary.clone()));
+        assert!(
+            disas.ends_with("mov cx, bx\ndb 0xf1 ; unknown opcode 0xf1 at 0x0002\nmov cx, 12\n"),
+            "{disas}"
+        );
+
+        if nasm_available() {
+            assert_eq!(assemble(&disas), binary);
+        }
+    }
 }
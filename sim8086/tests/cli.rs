@@ -0,0 +1,41 @@
+use std::process::Command;
+
+fn run(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_sim8086"))
+        .args(args)
+        .output()
+        .expect("Failed to run sim8086");
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn disasm_binary_file() {
+    let disas = run(&["--disasm", "inputs/listing_0038_many_register_mov.bin"]);
+
+    assert!(
+        disas.contains("bits 16\n\nmov cx, bx\nmov ch, ah\n"),
+        "{disas}"
+    );
+    assert_eq!(
+        disas.lines().filter(|line| line.starts_with("mov")).count(),
+        11
+    );
+}
+
+#[test]
+fn exec_binary_file() {
+    let trace = run(&[
+        "--disasm",
+        "inputs/listing_0049_conditional_jumps.bin",
+        "--exec",
+    ]);
+
+    assert!(trace.contains("jne $-6"), "{trace}");
+    assert!(trace.contains("hlt"), "{trace}");
+}
@@ -0,0 +1,56 @@
+use sim8086::{
+    disassemble_bytes,
+    parse::{Operand, Register},
+    simulate,
+};
+
+#[test]
+fn disassemble_fixture() {
+    let binary = std::fs::read("inputs/listing_0037_single_register_mov.bin").unwrap();
+
+    let disas = disassemble_bytes(&binary);
+    assert!(disas.starts_with("; This file was disassembled"));
+    assert!(disas.ends_with("bits 16\n\nmov cx, bx\n"), "{disas}");
+}
+
+#[test]
+fn simulate_fixture() {
+    let binary = std::fs::read("inputs/listing_0049_conditional_jumps.bin").unwrap();
+
+    let state = simulate(&binary);
+    assert_eq!(state.get_value(Operand::Reg(Register::BX)), 1030);
+    assert_eq!(state.get_value(Operand::Reg(Register::CX)), 0);
+}
+
+#[test]
+fn golden_mismatch_lists_differing_lines() {
+    let golden = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(golden.path(), "bits 16\n\nmov cx, bx\nmov ch, ah\n").unwrap();
+
+    sim8086::assert_matches_golden("bits 16\n\nmov cx, bx\nmov ch, ah\n", golden.path());
+
+    let err = std::panic::catch_unwind(|| {
+        sim8086::assert_matches_golden("bits 16\n\nmov cx, bx\nmov ch, al\n", golden.path())
+    })
+    .unwrap_err();
+
+    let message = err.downcast_ref::<String>().unwrap();
+    assert!(message.contains("line 4:\n  - mov ch, ah\n  + mov ch, al\n"), "{message}");
+    assert!(!message.contains("line 3:"), "{message}");
+}
+
+#[test]
+fn text_comparison_ignores_comments_and_spacing() {
+    let listing = "inputs/listing_0037_single_register_mov.asm";
+
+    let expected = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(expected.path(), "; a different header\nbits   16\n\n\n  mov  cx,  bx ; copy\n").unwrap();
+    sim8086::test_text_against_file(listing, expected.path());
+
+    std::fs::write(expected.path(), "bits 16\nmov cx, word bx\n").unwrap();
+    let err = std::panic::catch_unwind(|| sim8086::test_text_against_file(listing, expected.path()))
+        .unwrap_err();
+
+    let message = err.downcast_ref::<String>().unwrap();
+    assert!(message.contains("line 2:\n  - mov cx, word bx\n  + mov cx, bx\n"), "{message}");
+}